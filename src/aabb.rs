@@ -1,5 +1,4 @@
-use crate::ray::*;
-use cgmath::Point3;
+use cgmath::{Point3, Vector3};
 
 #[derive(Clone, Copy)]
 pub struct Aabb {
@@ -16,26 +15,25 @@ impl Aabb {
         self.minimum
     }
 
-    pub fn set_min(&mut self, min: Point3<f64>) {
-        self.minimum = min;
-    }
-
     pub fn max(&self) -> Point3<f64> {
         self.maximum
     }
 
-    pub fn set_max(&mut self, max: Point3<f64>) {
-        self.maximum = max;
-    }
-
-    pub fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> bool {
+    /// Slab test against a ray given by its origin and the reciprocal of its direction, which
+    /// a caller testing many boxes against the same ray computes only once.
+    pub fn hit_inverse(
+        &self,
+        origin: Point3<f64>,
+        inv_dir: Vector3<f64>,
+        mut t_min: f64,
+        mut t_max: f64,
+    ) -> bool {
         for a in 0..3 {
-            let inv_d = 1.0 / ray.direction()[a];
-            let t0 = (self.minimum[a] - ray.origin()[a]) * inv_d;
-            let t1 = (self.maximum[a] - ray.origin()[a]) * inv_d;
-            let (t0, t1) = if inv_d < 0.0 { (t1, t0) } else { (t0, t1) };
-            let t_min = t_min.max(t0);
-            let t_max = t_max.min(t1);
+            let t0 = (self.minimum[a] - origin[a]) * inv_dir[a];
+            let t1 = (self.maximum[a] - origin[a]) * inv_dir[a];
+            let (t0, t1) = if inv_dir[a] < 0.0 { (t1, t0) } else { (t0, t1) };
+            t_min = t_min.max(t0);
+            t_max = t_max.min(t1);
             if t_max <= t_min {
                 return false;
             }
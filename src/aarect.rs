@@ -1,5 +1,6 @@
 use crate::{aabb::Aabb, hitable::*, material::Material, ray::Ray};
-use cgmath::{Point3, Vector3};
+use cgmath::{InnerSpace, Point3, Vector3};
+use rand::{Rng, RngCore};
 
 pub struct XYRect<M: Material> {
     pub material: M,
@@ -11,7 +12,7 @@ pub struct XYRect<M: Material> {
 }
 
 impl<M: Material> Hittable for XYRect<M> {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let t = (self.k - ray.origin().z) / ray.direction().z;
         if t < t_min || t > t_max {
             return None;
@@ -43,6 +44,33 @@ impl<M: Material> Hittable for XYRect<M> {
         let maximum = Point3::new(self.x1, self.y1, self.k + 0.0001);
         Some(Aabb::new(minimum, maximum))
     }
+
+    fn pdf_value(&self, origin: Point3<f64>, direction: Vector3<f64>) -> f64 {
+        match self.hit(&Ray::new(origin, direction, 0.0), 0.001, f64::MAX) {
+            Some(record) => {
+                let area = (self.x1 - self.x0) * (self.y1 - self.y0);
+                let distance_squared = record.t * record.t * direction.magnitude2();
+                let cosine = (direction.dot(record.normal) / direction.magnitude()).abs();
+                distance_squared / (cosine * area)
+            }
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: Point3<f64>, rng: &mut dyn RngCore) -> Vector3<f64> {
+        let random_point = Point3::new(
+            self.x0 + rng.gen_range(0.0..1.0) * (self.x1 - self.x0),
+            self.y0 + rng.gen_range(0.0..1.0) * (self.y1 - self.y0),
+            self.k,
+        );
+        random_point - origin
+    }
+
+    fn lights<'a>(&'a self, lights: &mut Vec<Emitter<'a>>) {
+        if self.material.is_emissive() {
+            lights.push(Emitter::new(self));
+        }
+    }
 }
 
 pub struct XZRect<M: Material> {
@@ -55,7 +83,7 @@ pub struct XZRect<M: Material> {
 }
 
 impl<M: Material> Hittable for XZRect<M> {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let t = (self.k - ray.origin().y) / ray.direction().y;
         if t < t_min || t > t_max {
             return None;
@@ -87,6 +115,33 @@ impl<M: Material> Hittable for XZRect<M> {
         let maximum = Point3::new(self.x1, self.k + 0.0001, self.z1);
         Some(Aabb::new(minimum, maximum))
     }
+
+    fn pdf_value(&self, origin: Point3<f64>, direction: Vector3<f64>) -> f64 {
+        match self.hit(&Ray::new(origin, direction, 0.0), 0.001, f64::MAX) {
+            Some(record) => {
+                let area = (self.x1 - self.x0) * (self.z1 - self.z0);
+                let distance_squared = record.t * record.t * direction.magnitude2();
+                let cosine = (direction.dot(record.normal) / direction.magnitude()).abs();
+                distance_squared / (cosine * area)
+            }
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: Point3<f64>, rng: &mut dyn RngCore) -> Vector3<f64> {
+        let random_point = Point3::new(
+            self.x0 + rng.gen_range(0.0..1.0) * (self.x1 - self.x0),
+            self.k,
+            self.z0 + rng.gen_range(0.0..1.0) * (self.z1 - self.z0),
+        );
+        random_point - origin
+    }
+
+    fn lights<'a>(&'a self, lights: &mut Vec<Emitter<'a>>) {
+        if self.material.is_emissive() {
+            lights.push(Emitter::new(self));
+        }
+    }
 }
 
 pub struct YZRect<M: Material> {
@@ -99,7 +154,7 @@ pub struct YZRect<M: Material> {
 }
 
 impl<M: Material> Hittable for YZRect<M> {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let t = (self.k - ray.origin().x) / ray.direction().x;
         if t < t_min || t > t_max {
             return None;
@@ -131,4 +186,31 @@ impl<M: Material> Hittable for YZRect<M> {
         let maximum = Point3::new(self.k + 0.0001, self.y1, self.z1);
         Some(Aabb::new(minimum, maximum))
     }
+
+    fn pdf_value(&self, origin: Point3<f64>, direction: Vector3<f64>) -> f64 {
+        match self.hit(&Ray::new(origin, direction, 0.0), 0.001, f64::MAX) {
+            Some(record) => {
+                let area = (self.y1 - self.y0) * (self.z1 - self.z0);
+                let distance_squared = record.t * record.t * direction.magnitude2();
+                let cosine = (direction.dot(record.normal) / direction.magnitude()).abs();
+                distance_squared / (cosine * area)
+            }
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: Point3<f64>, rng: &mut dyn RngCore) -> Vector3<f64> {
+        let random_point = Point3::new(
+            self.k,
+            self.y0 + rng.gen_range(0.0..1.0) * (self.y1 - self.y0),
+            self.z0 + rng.gen_range(0.0..1.0) * (self.z1 - self.z0),
+        );
+        random_point - origin
+    }
+
+    fn lights<'a>(&'a self, lights: &mut Vec<Emitter<'a>>) {
+        if self.material.is_emissive() {
+            lights.push(Emitter::new(self));
+        }
+    }
 }
@@ -0,0 +1,70 @@
+use cgmath::{Quaternion, Vector3, VectorSpace};
+
+/// Values that can be blended between keyframes
+pub trait Interpolate: Copy {
+    /// Blend from `self` at `t` = 0 to `other` at `t` = 1.
+    fn interpolate(self, other: Self, t: f64) -> Self;
+}
+
+impl Interpolate for f64 {
+    fn interpolate(self, other: Self, t: f64) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Interpolate for Vector3<f64> {
+    fn interpolate(self, other: Self, t: f64) -> Self {
+        self.lerp(other, t)
+    }
+}
+
+/// Spherical linear interpolation, turning at a constant rate the shorter way
+impl Interpolate for Quaternion<f64> {
+    fn interpolate(self, other: Self, t: f64) -> Self {
+        self.slerp(other, t)
+    }
+}
+
+/// A value given at a number of times and interpolated in between. Before the first and
+/// after the last keyframe, it holds still.
+pub struct Keyframes<T> {
+    /// Sorted by time
+    keys: Vec<(f64, T)>,
+}
+
+impl<T: Interpolate> Keyframes<T> {
+    /// `None` without any keyframes.
+    pub fn new(mut keys: Vec<(f64, T)>) -> Option<Self> {
+        if keys.is_empty() {
+            return None;
+        }
+        keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Some(Self { keys })
+    }
+
+    pub fn at(&self, time: f64) -> T {
+        let next = self.keys.partition_point(|&(key, _)| key <= time);
+        if next == 0 {
+            return self.keys[0].1;
+        }
+        if next == self.keys.len() {
+            return self.keys[next - 1].1;
+        }
+        let (time0, value0) = self.keys[next - 1];
+        let (time1, value1) = self.keys[next];
+        value0.interpolate(value1, (time - time0) / (time1 - time0))
+    }
+
+    /// Times of the keyframes strictly between `time0` and `time1`.
+    pub fn times_between(&self, time0: f64, time1: f64) -> impl Iterator<Item = f64> + '_ {
+        self.keys
+            .iter()
+            .map(|&(time, _)| time)
+            .filter(move |&time| time0 < time && time < time1)
+    }
+
+    /// Time of the first and the last keyframe
+    pub fn span(&self) -> (f64, f64) {
+        (self.keys[0].0, self.keys[self.keys.len() - 1].0)
+    }
+}
@@ -1,31 +1,121 @@
 use crate::{aabb::Aabb, hitable::*, ray::Ray};
 use std::cmp::Ordering;
 
-enum BVHNode {
-    Branch { left: Box<Bvh>, right: Box<Bvh> },
-    Leaf(Box<dyn Hittable>),
+/// Relative cost of traversing a branch compared to intersecting one object.
+const TRAVERSAL_COST: f64 = 0.125;
+
+/// Strategy used to partition the objects of a `Bvh` node into its children.
+#[derive(Clone, Copy, Debug)]
+pub enum BvhSplit {
+    /// Sort along the longest axis and split at the median, one object per leaf.
+    Median,
+    /// Binned surface area heuristic: candidate splits between `bins` equally sized bins
+    /// along the longest centroid axis. Leaves hold up to `max_leaf_size` objects.
+    Sah { bins: usize, max_leaf_size: usize },
 }
 
-pub struct Bvh {
-    tree: BVHNode,
+impl Default for BvhSplit {
+    fn default() -> Self {
+        BvhSplit::Sah {
+            bins: 12,
+            max_leaf_size: 4,
+        }
+    }
+}
+
+/// Depth after which the SAH builder falls back to median splits. Median splits halve the
+/// number of objects, which bounds the tree depth and with it the traversal stack.
+const MAX_SAH_DEPTH: usize = 32;
+const STACK_SIZE: usize = 64;
+
+/// Intermediate pointer based tree, flattened into a `Bvh` once built.
+enum BuildTree {
+    Branch {
+        left: Box<BuildNode>,
+        right: Box<BuildNode>,
+        axis: usize,
+    },
+    Leaf(Vec<Box<dyn Hittable>>),
+}
+
+struct BuildNode {
+    tree: BuildTree,
     bbox: Aabb,
 }
 
-impl Bvh {
-    pub fn new(mut objects: Vec<Box<dyn Hittable>>, time0: f64, time1: f64) -> Self {
+fn bounding_box(object: &dyn Hittable, time0: f64, time1: f64) -> Aabb {
+    object
+        .bounding_box(time0, time1)
+        .unwrap_or_else(|| panic!("no bounding box in bvh node"))
+}
+
+fn surface_area(bbox: &Aabb) -> f64 {
+    let d = bbox.max() - bbox.min();
+    2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+}
+
+impl BuildNode {
+    fn new(
+        objects: Vec<Box<dyn Hittable>>,
+        time0: f64,
+        time1: f64,
+        split: BvhSplit,
+        depth: usize,
+    ) -> Self {
+        match objects.len() {
+            0 => panic!("no elements in scene"),
+            1 => Self::leaf(objects, time0, time1),
+            _ => match split {
+                BvhSplit::Sah {
+                    bins,
+                    max_leaf_size,
+                } if depth < MAX_SAH_DEPTH => Self::sah(
+                    objects,
+                    time0,
+                    time1,
+                    bins.max(2),
+                    max_leaf_size.max(1),
+                    depth,
+                ),
+                _ => Self::median(objects, time0, time1, depth),
+            },
+        }
+    }
+
+    fn leaf(objects: Vec<Box<dyn Hittable>>, time0: f64, time1: f64) -> Self {
+        let bbox = objects
+            .iter()
+            .map(|object| bounding_box(object.as_ref(), time0, time1))
+            .reduce(|acc, bbox| Aabb::surrounding_box(&acc, &bbox))
+            .unwrap();
+        BuildNode {
+            tree: BuildTree::Leaf(objects),
+            bbox,
+        }
+    }
+
+    fn branch(left: BuildNode, right: BuildNode, axis: usize) -> Self {
+        let bbox = Aabb::surrounding_box(&left.bbox, &right.bbox);
+        BuildNode {
+            tree: BuildTree::Branch {
+                left: Box::new(left),
+                right: Box::new(right),
+                axis,
+            },
+            bbox,
+        }
+    }
+
+    fn median(mut objects: Vec<Box<dyn Hittable>>, time0: f64, time1: f64, depth: usize) -> Self {
         fn box_compare(
             time0: f64,
             time1: f64,
             axis: usize,
         ) -> impl FnMut(&Box<dyn Hittable>, &Box<dyn Hittable>) -> Ordering {
             move |a, b| {
-                let a_box = a.bounding_box(time0, time1);
-                let b_box = b.bounding_box(time0, time1);
-                if let (Some(a), Some(b)) = (a_box, b_box) {
-                    a.min()[axis].partial_cmp(&b.min()[axis]).unwrap()
-                } else {
-                    panic!("no bounding box in bvh node")
-                }
+                let a = bounding_box(a.as_ref(), time0, time1);
+                let b = bounding_box(b.as_ref(), time0, time1);
+                a.min()[axis].total_cmp(&b.min()[axis])
             }
         }
 
@@ -37,12 +127,9 @@ impl Bvh {
         ) -> f64 {
             let (min, max) = objects
                 .iter()
-                .fold((f64::MIN, f64::MAX), |(bmin, bmax), hit| {
-                    if let Some(bbox) = hit.bounding_box(time0, time1) {
-                        (bmin.min(bbox.min()[axis]), bmin.max(bbox.max()[axis]))
-                    } else {
-                        (bmin, bmax)
-                    }
+                .fold((f64::MAX, f64::MIN), |(bmin, bmax), hit| {
+                    let bbox = bounding_box(hit.as_ref(), time0, time1);
+                    (bmin.min(bbox.min()[axis]), bmax.max(bbox.max()[axis]))
                 });
             max - min
         }
@@ -51,65 +138,365 @@ impl Bvh {
             .map(|a| (a, axis_range(&objects, time0, time1, a)))
             .collect();
 
-        axis_ranges.sort_unstable_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        axis_ranges.sort_unstable_by(|a, b| b.1.total_cmp(&a.1));
         let axis = axis_ranges[0].0;
 
         objects.sort_by(box_compare(time0, time1, axis));
         let len = objects.len();
-        match len {
-            0 => panic!("no elements in scene"),
-            1 => {
-                let leaf = objects.pop().unwrap();
-                if let Some(bbox) = leaf.bounding_box(time0, time1) {
-                    Bvh {
-                        tree: BVHNode::Leaf(leaf),
-                        bbox,
+        let right = BuildNode::new(
+            objects.drain(len / 2..).collect(),
+            time0,
+            time1,
+            BvhSplit::Median,
+            depth + 1,
+        );
+        let left = BuildNode::new(objects, time0, time1, BvhSplit::Median, depth + 1);
+        Self::branch(left, right, axis)
+    }
+
+    fn sah(
+        objects: Vec<Box<dyn Hittable>>,
+        time0: f64,
+        time1: f64,
+        bins: usize,
+        max_leaf_size: usize,
+        depth: usize,
+    ) -> Self {
+        let split = BvhSplit::Sah {
+            bins,
+            max_leaf_size,
+        };
+        let boxes: Vec<Aabb> = objects
+            .iter()
+            .map(|object| bounding_box(object.as_ref(), time0, time1))
+            .collect();
+        let bbox = boxes
+            .iter()
+            .skip(1)
+            .fold(boxes[0], |acc, bbox| Aabb::surrounding_box(&acc, bbox));
+        let centroids: Vec<_> = boxes
+            .iter()
+            .map(|bbox| bbox.min() + (bbox.max() - bbox.min()) / 2.0)
+            .collect();
+
+        // Bin along the axis with the largest centroid extent
+        let (cmin, cmax) = centroids
+            .iter()
+            .skip(1)
+            .fold((centroids[0], centroids[0]), |(cmin, cmax), c| {
+                (cmin.zip(*c, f64::min), cmax.zip(*c, f64::max))
+            });
+        let extent = cmax - cmin;
+        let axis = (0..3)
+            .max_by(|a, b| extent[*a].total_cmp(&extent[*b]))
+            .unwrap();
+
+        let n = objects.len();
+        if extent[axis] <= 0.0 {
+            // All centroids coincide, no split can separate them
+            return if n <= max_leaf_size {
+                Self::leaf(objects, time0, time1)
+            } else {
+                Self::median(objects, time0, time1, depth)
+            };
+        }
+
+        let bin_of =
+            |c: f64| (((c - cmin[axis]) / extent[axis] * bins as f64) as usize).min(bins - 1);
+        let mut bin_counts = vec![0usize; bins];
+        let mut bin_boxes: Vec<Option<Aabb>> = vec![None; bins];
+        for (bbox, centroid) in boxes.iter().zip(centroids.iter()) {
+            let b = bin_of(centroid[axis]);
+            bin_counts[b] += 1;
+            bin_boxes[b] = Some(match bin_boxes[b] {
+                Some(acc) => Aabb::surrounding_box(&acc, bbox),
+                None => *bbox,
+            });
+        }
+
+        // Sweep from both sides to get area and count left and right of each split plane
+        let sweep = |range: &mut dyn Iterator<Item = usize>| {
+            let mut acc: Option<Aabb> = None;
+            let mut count = 0;
+            range
+                .map(|b| {
+                    if let Some(bbox) = bin_boxes[b] {
+                        acc = Some(acc.map_or(bbox, |acc| Aabb::surrounding_box(&acc, &bbox)));
                     }
-                } else {
-                    panic!("no bounding box in bvh node")
-                }
+                    count += bin_counts[b];
+                    (acc.as_ref().map_or(0.0, surface_area), count)
+                })
+                .collect::<Vec<_>>()
+        };
+        let left = sweep(&mut (0..bins - 1));
+        let mut right = sweep(&mut (1..bins).rev());
+        right.reverse();
+
+        let area = surface_area(&bbox);
+        let (split_bin, split_cost) = left
+            .iter()
+            .zip(right.iter())
+            .enumerate()
+            .filter(|(_, ((_, nl), (_, nr)))| *nl > 0 && *nr > 0)
+            .map(|(b, ((al, nl), (ar, nr)))| {
+                let cost = TRAVERSAL_COST + (al * *nl as f64 + ar * *nr as f64) / area;
+                (b + 1, cost)
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .expect("centroids spread over multiple bins");
+
+        if n <= max_leaf_size && n as f64 <= split_cost {
+            return Self::leaf(objects, time0, time1);
+        }
+
+        let (left, right): (Vec<_>, Vec<_>) = objects
+            .into_iter()
+            .zip(centroids)
+            .partition(|(_, c)| bin_of(c[axis]) < split_bin);
+        let left = left.into_iter().map(|(object, _)| object).collect();
+        let right = right.into_iter().map(|(object, _)| object).collect();
+        Self::branch(
+            BuildNode::new(left, time0, time1, split, depth + 1),
+            BuildNode::new(right, time0, time1, split, depth + 1),
+            axis,
+        )
+    }
+}
+
+enum NodeKind {
+    /// `count` objects starting at `first` in `Bvh::objects`
+    Leaf { first: u32, count: u32 },
+    /// The first child directly follows its parent, the second one is at `second_child`.
+    /// `axis` is the axis the children were split along.
+    Branch { second_child: u32, axis: u8 },
+}
+
+struct LinearNode {
+    bbox: Aabb,
+    kind: NodeKind,
+}
+
+/// Bounding volume hierarchy stored as a contiguous array of nodes in depth-first order.
+pub struct Bvh {
+    nodes: Vec<LinearNode>,
+    objects: Vec<Box<dyn Hittable>>,
+}
+
+impl Bvh {
+    pub fn new(objects: Vec<Box<dyn Hittable>>, time0: f64, time1: f64) -> Self {
+        Self::with_split(objects, time0, time1, BvhSplit::Median)
+    }
+
+    pub fn with_split(
+        objects: Vec<Box<dyn Hittable>>,
+        time0: f64,
+        time1: f64,
+        split: BvhSplit,
+    ) -> Self {
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(2 * objects.len()),
+            objects: Vec::with_capacity(objects.len()),
+        };
+        bvh.flatten(BuildNode::new(objects, time0, time1, split, 0));
+        bvh
+    }
+
+    fn flatten(&mut self, node: BuildNode) {
+        let index = self.nodes.len();
+        match node.tree {
+            BuildTree::Leaf(objects) => {
+                self.nodes.push(LinearNode {
+                    bbox: node.bbox,
+                    kind: NodeKind::Leaf {
+                        first: self.objects.len() as u32,
+                        count: objects.len() as u32,
+                    },
+                });
+                self.objects.extend(objects);
             }
-            _ => {
-                let right = Bvh::new(objects.drain(len / 2..).collect(), time0, time1);
-                let left = Bvh::new(objects, time0, time1);
-                let bbox = Aabb::surrounding_box(&left.bbox, &right.bbox);
-
-                Bvh {
-                    tree: BVHNode::Branch {
-                        left: Box::new(left),
-                        right: Box::new(right),
+            BuildTree::Branch { left, right, axis } => {
+                self.nodes.push(LinearNode {
+                    bbox: node.bbox,
+                    kind: NodeKind::Branch {
+                        second_child: 0,
+                        axis: axis as u8,
                     },
-                    bbox,
+                });
+                self.flatten(*left);
+                let second = self.nodes.len() as u32;
+                if let NodeKind::Branch { second_child, .. } = &mut self.nodes[index].kind {
+                    *second_child = second;
                 }
+                self.flatten(*right);
             }
         }
     }
+
+    /// Expected cost of intersecting a random ray with this tree according to the surface
+    /// area heuristic, in units of object intersections.
+    pub fn cost(&self) -> f64 {
+        let weighted_cost: f64 = self
+            .nodes
+            .iter()
+            .map(|node| {
+                surface_area(&node.bbox)
+                    * match node.kind {
+                        NodeKind::Leaf { count, .. } => count as f64,
+                        NodeKind::Branch { .. } => TRAVERSAL_COST,
+                    }
+            })
+            .sum();
+        weighted_cost / surface_area(&self.nodes[0].bbox)
+    }
 }
 
 impl Hittable for Bvh {
-    fn hit(&self, ray: &Ray, t_min: f64, mut t_max: f64) -> Option<HitRecord> {
-        if self.bbox.hit(ray, t_min, t_max) {
-            match &self.tree {
-                BVHNode::Leaf(leaf) => leaf.hit(ray, t_min, t_max),
-                BVHNode::Branch { left, right } => {
-                    let left = left.hit(ray, t_min, t_max);
-                    if let Some(l) = &left {
-                        t_max = l.t
-                    };
-                    let right = right.hit(ray, t_min, t_max);
-                    if right.is_some() {
-                        right
-                    } else {
-                        left
+    fn hit(&self, ray: &Ray, t_min: f64, mut t_max: f64) -> Option<HitRecord<'_>> {
+        let origin = ray.origin();
+        let inv_dir = ray.direction().map(|d| 1.0 / d);
+        let dir_is_neg = [inv_dir.x < 0.0, inv_dir.y < 0.0, inv_dir.z < 0.0];
+
+        let mut hit_anything = None;
+        let mut stack = [0u32; STACK_SIZE];
+        let mut stack_len = 0;
+        let mut current = 0;
+        loop {
+            let node = &self.nodes[current as usize];
+            if node.bbox.hit_inverse(origin, inv_dir, t_min, t_max) {
+                match node.kind {
+                    NodeKind::Leaf { first, count } => {
+                        let first = first as usize;
+                        for object in &self.objects[first..first + count as usize] {
+                            if let Some(record) = object.hit(ray, t_min, t_max) {
+                                t_max = record.t;
+                                hit_anything = Some(record);
+                            }
+                        }
+                    }
+                    NodeKind::Branch { second_child, axis } => {
+                        // Visit the child closer to the ray origin first, so hits found there
+                        // shrink t_max for the other one.
+                        let (near, far) = if dir_is_neg[axis as usize] {
+                            (second_child, current + 1)
+                        } else {
+                            (current + 1, second_child)
+                        };
+                        stack[stack_len] = far;
+                        stack_len += 1;
+                        current = near;
+                        continue;
                     }
                 }
             }
-        } else {
-            None
+            if stack_len == 0 {
+                break;
+            }
+            stack_len -= 1;
+            current = stack[stack_len];
         }
+        hit_anything
     }
 
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
-        Some(self.bbox)
+        Some(self.nodes[0].bbox)
+    }
+
+    fn lights<'a>(&'a self, lights: &mut Vec<Emitter<'a>>) {
+        for object in &self.objects {
+            object.lights(lights);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        color::Color, hitable_list::HitableList, material::Lambertian, sphere::Sphere,
+        texture::SolidColor,
+    };
+    use cgmath::Point3;
+    use rand::{Rng, RngCore, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+    use raytracer::random_unit_vector;
+    use std::sync::Arc;
+
+    /// Clusters of spheres of varying size, so that median and SAH splits differ
+    fn spheres(rng: &mut dyn RngCore) -> Vec<(Point3<f64>, f64)> {
+        (0..8)
+            .flat_map(|_| {
+                let cluster = Point3::new(
+                    rng.gen_range(-10.0..10.0),
+                    rng.gen_range(-10.0..10.0),
+                    rng.gen_range(-10.0..10.0),
+                );
+                let spread = rng.gen_range(0.1..3.0);
+                (0..rng.gen_range(1..40))
+                    .map(|_| {
+                        let offset = spread * random_unit_vector(rng) * rng.gen_range(0.0..1.0);
+                        (cluster + offset, rng.gen_range(0.01..0.5))
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    fn objects(spheres: &[(Point3<f64>, f64)]) -> Vec<Box<dyn Hittable>> {
+        let material = Arc::new(Lambertian::new(Arc::new(SolidColor::new(&Color::new(
+            0.5, 0.5, 0.5,
+        )))));
+        spheres
+            .iter()
+            .map(|&(center, radius)| {
+                Box::new(Sphere::new(center, radius, material.clone())) as Box<dyn Hittable>
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nearest_hit_matches_list() {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let mut hits = 0;
+        for _ in 0..10 {
+            let spheres = spheres(&mut rng);
+            let list = HitableList {
+                objects: objects(&spheres),
+            };
+            let bvhs = [
+                Bvh::with_split(objects(&spheres), 0.0, 1.0, BvhSplit::Median),
+                Bvh::with_split(objects(&spheres), 0.0, 1.0, BvhSplit::default()),
+                Bvh::with_split(
+                    objects(&spheres),
+                    0.0,
+                    1.0,
+                    BvhSplit::Sah {
+                        bins: 4,
+                        max_leaf_size: 1,
+                    },
+                ),
+            ];
+
+            for _ in 0..2000 {
+                let origin = Point3::new(
+                    rng.gen_range(-15.0..15.0),
+                    rng.gen_range(-15.0..15.0),
+                    rng.gen_range(-15.0..15.0),
+                );
+                // Aim close to a random sphere, so that most rays hit something.
+                let (center, radius) = spheres[rng.gen_range(0..spheres.len())];
+                let target = center + 1.5 * radius * random_unit_vector(&mut rng);
+                let ray = Ray::new(origin, target - origin, 0.0);
+                let expected = list.hit(&ray, 0.001, f64::MAX).map(|record| record.t);
+                hits += expected.is_some() as usize;
+                for bvh in &bvhs {
+                    assert_eq!(
+                        bvh.hit(&ray, 0.001, f64::MAX).map(|record| record.t),
+                        expected
+                    );
+                }
+            }
+        }
+        // Enough rays hit something to make the comparison meaningful.
+        assert!(hits > 10000, "only {} hits", hits);
     }
 }
@@ -1,36 +1,135 @@
-use crate::ray::Ray;
+use crate::{
+    color::{self, Color},
+    distribution::Distribution2D,
+    ray::Ray,
+};
 use cgmath::{InnerSpace, Point3, Vector3};
+use image::ImageResult;
 use rand::{Rng, RngCore};
 use raytracer::random_in_unit_disk;
+use std::{f64::consts::PI, path::Path};
 
-pub struct Camera {
+/// Projection from the image to the rays leaving the camera.
+pub trait Camera: Send + Sync {
+    /// Ray through the image at `(s, t)` in [0, 1]², measured from the bottom left corner.
+    fn get_ray(&self, s: f64, t: f64, rng: &mut dyn RngCore) -> Ray;
+}
+
+/// Random time while the shutter is open, `time0` for an instantaneous exposure
+fn shutter(time0: f64, time1: f64, rng: &mut dyn RngCore) -> f64 {
+    if time1 > time0 {
+        rng.gen_range(time0..time1)
+    } else {
+        time0
+    }
+}
+
+/// Shape of the lens opening, which is also the shape of out of focus highlights (bokeh).
+pub enum Aperture {
+    Disk,
+    /// Regular polygon with `blades` corners, turned by `rotation` radians
+    Polygon {
+        blades: usize,
+        rotation: f64,
+    },
+    /// Grayscale mask covering the square around the lens, see `Aperture::image`
+    Image(Distribution2D),
+}
+
+impl Aperture {
+    /// Load a mask whose brightness is the transparency of the opening. It is stretched over
+    /// the square enclosing the lens, upright as seen from behind the camera.
+    pub fn image<P: AsRef<Path>>(path: P) -> ImageResult<Self> {
+        let image = image::open(path)?.into_rgb32f();
+        let (width, height) = image.dimensions();
+        let weights: Vec<f64> = image
+            .pixels()
+            .map(|pixel| {
+                color::luminance(Color::new(
+                    pixel[0] as f64,
+                    pixel[1] as f64,
+                    pixel[2] as f64,
+                ))
+            })
+            .collect();
+        Ok(Aperture::Image(Distribution2D::new(
+            &weights,
+            width as usize,
+            height as usize,
+        )))
+    }
+
+    /// Random point of the opening within the unit circle or square.
+    fn sample(&self, rng: &mut dyn RngCore) -> (f64, f64) {
+        match self {
+            Aperture::Disk => {
+                let p = random_in_unit_disk(rng);
+                (p.x, p.y)
+            }
+            Aperture::Polygon { blades, rotation } => {
+                // Uniform in one of the triangles between the center and two adjacent corners
+                let sector = 2.0 * PI / *blades as f64;
+                let angle = rotation + sector * rng.gen_range(0..*blades) as f64;
+                let (a, b) = (rng.gen_range(0.0..1.0f64).sqrt(), rng.gen_range(0.0..1.0));
+                let x = a * (1.0 - b) + a * b * sector.cos();
+                let y = a * b * sector.sin();
+                (
+                    x * angle.cos() - y * angle.sin(),
+                    x * angle.sin() + y * angle.cos(),
+                )
+            }
+            Aperture::Image(distribution) => {
+                let (x, y) = distribution.sample(rng);
+                (2.0 * x - 1.0, 1.0 - 2.0 * y)
+            }
+        }
+    }
+}
+
+/// Thin lens: rays leave from a random point of the opening and all meet again at
+/// `focus_dist`, where the image is sharp.
+pub struct Lens {
+    pub aperture: Aperture,
+    pub radius: f64,
+    pub focus_dist: f64,
+}
+
+impl Lens {
+    /// Random offset from the lens center, which spans the unit vectors `u` and `v`.
+    fn offset(&self, u: Vector3<f64>, v: Vector3<f64>, rng: &mut dyn RngCore) -> Vector3<f64> {
+        let (x, y) = self.aperture.sample(rng);
+        self.radius * (u * x + v * y)
+    }
+}
+
+/// Pinhole or thin lens camera. Straight lines stay straight.
+pub struct Perspective {
     origin: Point3<f64>,
     lower_left_corner: Point3<f64>,
     horizontal: Vector3<f64>,
     vertical: Vector3<f64>,
     u: Vector3<f64>,
     v: Vector3<f64>,
-    #[allow(dead_code)]
-    w: Vector3<f64>,
-    lens_radius: f64,
+    lens: Lens,
     /// shutter open time
     time0: f64,
     /// shutter close time
     time1: f64,
 }
 
-impl Camera {
+impl Perspective {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         lookfrom: Point3<f64>,
         lookat: Point3<f64>,
         vup: Vector3<f64>,
         vfov: f64,
         aspect_ratio: f64,
-        aperture: f64,
-        focus_dist: f64,
+        lens: Lens,
         time0: f64,
         time1: f64,
     ) -> Self {
+        let focus_dist = lens.focus_dist;
         let theta = vfov.to_radians();
         let h = (theta / 2.0).tan();
         let viewport_height = 2.0 * h;
@@ -44,7 +143,6 @@ impl Camera {
         let horizontal = focus_dist * viewport_width * u;
         let vertical = focus_dist * viewport_height * v;
         let lower_left_corner = origin - horizontal / 2.0 - vertical / 2.0 - focus_dist * w;
-        let lens_radius = aperture / 2.0;
 
         Self {
             origin,
@@ -53,23 +151,253 @@ impl Camera {
             vertical,
             u,
             v,
-            w,
-            lens_radius,
+            lens,
             time0,
             time1,
         }
     }
+}
 
-    pub fn get_ray(&self, s: f64, t: f64, rng: &mut dyn RngCore) -> Ray {
-        let rd = self.lens_radius * random_in_unit_disk(rng);
-        let offset = self.u * rd.x + self.v * rd.y;
+impl Camera for Perspective {
+    fn get_ray(&self, s: f64, t: f64, rng: &mut dyn RngCore) -> Ray {
+        let offset = self.lens.offset(self.u, self.v, rng);
         let direction =
             self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset;
 
         Ray::new(
             self.origin + offset,
             direction,
-            rng.gen_range(self.time0..self.time1),
+            shutter(self.time0, self.time1, rng),
+        )
+    }
+}
+
+/// Parallel rays along the view direction, so sizes do not shrink with distance.
+pub struct Orthographic {
+    lower_left_corner: Point3<f64>,
+    horizontal: Vector3<f64>,
+    vertical: Vector3<f64>,
+    direction: Vector3<f64>,
+    time0: f64,
+    time1: f64,
+}
+
+impl Orthographic {
+    /// `height` is the extent of the view in scene units.
+    pub fn new(
+        lookfrom: Point3<f64>,
+        lookat: Point3<f64>,
+        vup: Vector3<f64>,
+        height: f64,
+        aspect_ratio: f64,
+        time0: f64,
+        time1: f64,
+    ) -> Self {
+        let w = (lookfrom - lookat).normalize();
+        let u = vup.cross(w).normalize();
+        let v = w.cross(u);
+
+        let horizontal = aspect_ratio * height * u;
+        let vertical = height * v;
+        Self {
+            lower_left_corner: lookfrom - horizontal / 2.0 - vertical / 2.0,
+            horizontal,
+            vertical,
+            direction: -w,
+            time0,
+            time1,
+        }
+    }
+}
+
+impl Camera for Orthographic {
+    fn get_ray(&self, s: f64, t: f64, rng: &mut dyn RngCore) -> Ray {
+        Ray::new(
+            self.lower_left_corner + s * self.horizontal + t * self.vertical,
+            self.direction,
+            shutter(self.time0, self.time1, rng),
+        )
+    }
+}
+
+/// Equidistant fisheye: the angle to the view direction grows linearly with the distance
+/// from the image center. The projection continues past the field of view up to looking
+/// straight back.
+pub struct Fisheye {
+    origin: Point3<f64>,
+    u: Vector3<f64>,
+    v: Vector3<f64>,
+    w: Vector3<f64>,
+    /// Angle per image height, in radians
+    fov: f64,
+    aspect_ratio: f64,
+    time0: f64,
+    time1: f64,
+}
+
+impl Fisheye {
+    /// `fov` is the angle across the image height in degrees, 180 for a circular fisheye
+    /// touching the top and bottom edges.
+    pub fn new(
+        lookfrom: Point3<f64>,
+        lookat: Point3<f64>,
+        vup: Vector3<f64>,
+        fov: f64,
+        aspect_ratio: f64,
+        time0: f64,
+        time1: f64,
+    ) -> Self {
+        let w = (lookfrom - lookat).normalize();
+        let u = vup.cross(w).normalize();
+        let v = w.cross(u);
+        Self {
+            origin: lookfrom,
+            u,
+            v,
+            w,
+            fov: fov.to_radians(),
+            aspect_ratio,
+            time0,
+            time1,
+        }
+    }
+}
+
+impl Camera for Fisheye {
+    fn get_ray(&self, s: f64, t: f64, rng: &mut dyn RngCore) -> Ray {
+        let x = (s - 0.5) * self.aspect_ratio;
+        let y = t - 0.5;
+        let theta = ((x * x + y * y).sqrt() * self.fov).min(PI);
+        let phi = y.atan2(x);
+        let direction =
+            theta.sin() * (phi.cos() * self.u + phi.sin() * self.v) - theta.cos() * self.w;
+        Ray::new(self.origin, direction, shutter(self.time0, self.time1, rng))
+    }
+}
+
+/// Full 360×180 degree panorama in the equirectangular layout of environment maps: the top
+/// row looks straight up along `vup`, the center column towards `lookat`, and the image wraps
+/// around horizontally. Best rendered at an aspect ratio of 2:1.
+pub struct Equirectangular {
+    origin: Point3<f64>,
+    /// Level view direction
+    forward: Vector3<f64>,
+    right: Vector3<f64>,
+    up: Vector3<f64>,
+    time0: f64,
+    time1: f64,
+}
+
+impl Equirectangular {
+    pub fn new(
+        lookfrom: Point3<f64>,
+        lookat: Point3<f64>,
+        vup: Vector3<f64>,
+        time0: f64,
+        time1: f64,
+    ) -> Self {
+        let up = vup.normalize();
+        // Only the heading of `lookat` counts, the horizon stays level
+        let right = (lookat - lookfrom).cross(up).normalize();
+        Self {
+            origin: lookfrom,
+            forward: up.cross(right),
+            right,
+            up,
+            time0,
+            time1,
+        }
+    }
+}
+
+impl Camera for Equirectangular {
+    fn get_ray(&self, s: f64, t: f64, rng: &mut dyn RngCore) -> Ray {
+        let phi = 2.0 * PI * (s - 0.5);
+        let elevation = PI * (t - 0.5);
+        let direction = elevation.cos() * (phi.cos() * self.forward + phi.sin() * self.right)
+            + elevation.sin() * self.up;
+        Ray::new(self.origin, direction, shutter(self.time0, self.time1, rng))
+    }
+}
+
+/// Omni-directional stereo for VR headsets: equirectangular panoramas for the left eye in the
+/// top half of the image and the right eye in the bottom half, laid out like
+/// `Equirectangular`. For every direction, the eyes sit on either side of `lookfrom` across
+/// the line of sight, as if the viewer had turned their head to look there. Towards the poles
+/// the eyes move together to avoid a swirling disparity. Each eye is a thin lens focused at
+/// `focus_dist` along the line of sight. Best rendered at an aspect ratio of 1:1.
+pub struct Ods {
+    origin: Point3<f64>,
+    forward: Vector3<f64>,
+    right: Vector3<f64>,
+    up: Vector3<f64>,
+    /// Half the interpupillary distance
+    eye_offset: f64,
+    /// Distance at which the lines of sight of both eyes cross, parallel if `None`
+    convergence: Option<f64>,
+    lens: Lens,
+    time0: f64,
+    time1: f64,
+}
+
+impl Ods {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        lookfrom: Point3<f64>,
+        lookat: Point3<f64>,
+        vup: Vector3<f64>,
+        ipd: f64,
+        convergence: Option<f64>,
+        lens: Lens,
+        time0: f64,
+        time1: f64,
+    ) -> Self {
+        let panorama = Equirectangular::new(lookfrom, lookat, vup, time0, time1);
+        Self {
+            origin: panorama.origin,
+            forward: panorama.forward,
+            right: panorama.right,
+            up: panorama.up,
+            eye_offset: ipd / 2.0,
+            convergence,
+            lens,
+            time0,
+            time1,
+        }
+    }
+}
+
+impl Camera for Ods {
+    fn get_ray(&self, s: f64, t: f64, rng: &mut dyn RngCore) -> Ray {
+        let (side, t) = if t >= 0.5 {
+            (-1.0, 2.0 * t - 1.0)
+        } else {
+            (1.0, 2.0 * t)
+        };
+        let phi = 2.0 * PI * (s - 0.5);
+        let elevation = PI * (t - 0.5);
+        let heading = phi.cos() * self.forward + phi.sin() * self.right;
+        let sideways = phi.cos() * self.right - phi.sin() * self.forward;
+        let line_of_sight = elevation.cos() * heading + elevation.sin() * self.up;
+
+        let eye = self.origin + side * self.eye_offset * elevation.cos() * sideways;
+        let direction = match self.convergence {
+            Some(distance) => (self.origin + distance * line_of_sight - eye).normalize(),
+            None => line_of_sight,
+        };
+        // Lens frame across the ray, `u` stays horizontal
+        let u = direction.cross(self.up);
+        let u = if u.magnitude2() > 0.0 {
+            u.normalize()
+        } else {
+            sideways
+        };
+        let v = u.cross(direction);
+        let offset = self.lens.offset(u, v, rng);
+        Ray::new(
+            eye + offset,
+            self.lens.focus_dist * direction - offset,
+            shutter(self.time0, self.time1, rng),
         )
     }
 }
@@ -3,22 +3,6 @@ use rand::{Rng, RngCore};
 
 pub type Color = Vector3<f64>;
 
-// Write the translated [0,255] value of each color component.
-pub fn pnm_color(color: Color, samples_per_pixel: usize) -> String {
-    let scale = 1.0 / samples_per_pixel as f64;
-    // Divide the color by the number of samples and gamma-correct for gamma=2.0.
-    let r = (color.x * scale).sqrt();
-    let g = (color.y * scale).sqrt();
-    let b = (color.z * scale).sqrt();
-
-    format!(
-        "{} {} {}\n",
-        (256.0 * r.clamp(0.0, 0.999)) as u8,
-        (256.0 * g.clamp(0.0, 0.999)) as u8,
-        (256.0 * b.clamp(0.0, 0.999)) as u8
-    )
-}
-
 pub fn random_color(rng: &mut dyn RngCore) -> Color {
     let x = rng.gen_range(0.0..1.0);
     let y = rng.gen_range(0.0..1.0);
@@ -41,3 +25,8 @@ pub fn white() -> Color {
         z: 1.0,
     }
 }
+
+/// Relative luminance of a linear Rec. 709 color
+pub fn luminance(color: Color) -> f64 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
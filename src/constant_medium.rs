@@ -1,26 +1,49 @@
 use crate::{aabb::Aabb, hitable::*, material::*, ray::Ray, texture::*};
 use cgmath::{InnerSpace, Vector3};
-use rand::prelude::*;
+use std::sync::Arc;
 
 pub struct ConstantMedium<H: Hittable> {
     density: f64,
     boundary: H,
-    phase_function: Box<dyn Material>,
+    phase_function: Arc<dyn Material>,
 }
 
 impl<H: Hittable> ConstantMedium<H> {
-    pub fn new<T: Texture + 'static>(boundary: H, density: f64, texture: T) -> Self {
+    pub fn new(boundary: H, density: f64, texture: Arc<dyn Texture>) -> Self {
         Self {
             density,
             boundary,
-            phase_function: Box::new(Isotropic::new(Box::new(texture))),
+            phase_function: Arc::new(Isotropic::new(texture)),
         }
     }
 }
 
+/// Uniformly distributed number in [0, 1) derived from the ray alone, so that renders stay
+/// reproducible no matter which thread traces the ray.
+fn ray_random(ray: &Ray) -> f64 {
+    // SplitMix64 finalizer
+    fn mix(mut z: u64) -> u64 {
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+    let (origin, direction) = (ray.origin(), ray.direction());
+    let hash = [
+        origin.x,
+        origin.y,
+        origin.z,
+        direction.x,
+        direction.y,
+        direction.z,
+        ray.time(),
+    ]
+    .iter()
+    .fold(0x9e3779b97f4a7c15, |hash, x| mix(hash ^ x.to_bits()));
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
 impl<H: Hittable> Hittable for ConstantMedium<H> {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let mut rng = thread_rng();
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         if let Some(mut rec1) = self.boundary.hit(ray, f64::MIN, f64::MAX) {
             if let Some(mut rec2) = self.boundary.hit(ray, rec1.t + 0.0001, f64::MAX) {
                 rec1.t = rec1.t.max(t_min);
@@ -29,8 +52,7 @@ impl<H: Hittable> Hittable for ConstantMedium<H> {
                     rec1.t = rec1.t.max(0.0);
                     let ray_length = ray.direction().magnitude();
                     let distance_inside_boundary = (rec2.t - rec1.t) * ray_length;
-                    let hit_distance =
-                        -(1.0 / self.density) * (rng.gen_range(0.0..1.0) as f64).ln();
+                    let hit_distance = -(1.0 / self.density) * ray_random(ray).ln();
                     if hit_distance <= distance_inside_boundary {
                         let t = rec1.t + hit_distance / ray_length;
                         return Some(HitRecord {
@@ -75,11 +75,15 @@ impl Cuboid {
 }
 
 impl Hittable for Cuboid {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         self.sides.hit(ray, t_min, t_max)
     }
 
     fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
         Some(Aabb::new(self.min, self.max))
     }
+
+    fn lights<'a>(&'a self, lights: &mut Vec<Emitter<'a>>) {
+        self.sides.lights(lights)
+    }
 }
@@ -0,0 +1,158 @@
+use rand::{Rng, RngCore};
+
+/// Piecewise constant distribution over [0, 1), with one segment per weight.
+pub struct Distribution1D {
+    weights: Vec<f64>,
+    /// Running sum of the normalized weights, starting at 0 and ending at 1
+    cdf: Vec<f64>,
+    total: f64,
+}
+
+impl Distribution1D {
+    /// If all weights are zero, every segment is equally likely.
+    pub fn new(weights: Vec<f64>) -> Self {
+        let total: f64 = weights.iter().sum();
+        let n = weights.len() as f64;
+        let mut cdf = Vec::with_capacity(weights.len() + 1);
+        cdf.push(0.0);
+        for (i, weight) in weights.iter().enumerate() {
+            let step = if total > 0.0 { weight / total } else { 1.0 / n };
+            cdf.push(cdf[i] + step);
+        }
+        Self {
+            weights,
+            cdf,
+            total,
+        }
+    }
+
+    /// Sum of the weights.
+    pub fn total(&self) -> f64 {
+        self.total
+    }
+
+    /// Sample a point, returning it with the index of its segment.
+    pub fn sample(&self, rng: &mut dyn RngCore) -> (f64, usize) {
+        let u = rng.gen_range(0.0..1.0);
+        // The last segment whose start is at most u, skipping empty segments
+        let index = (self.cdf.partition_point(|&c| c <= u) - 1).min(self.weights.len() - 1);
+        let width = self.cdf[index + 1] - self.cdf[index];
+        let offset = if width > 0.0 {
+            ((u - self.cdf[index]) / width).min(1.0 - f64::EPSILON)
+        } else {
+            0.0
+        };
+        ((index as f64 + offset) / self.weights.len() as f64, index)
+    }
+
+    /// Density of `sample` in the segment `index`.
+    pub fn pdf(&self, index: usize) -> f64 {
+        (self.cdf[index + 1] - self.cdf[index]) * self.weights.len() as f64
+    }
+}
+
+/// Piecewise constant distribution over [0, 1)², given by a grid of weights stored row by
+/// row. Rows are chosen by their total weight, then a column within the row.
+pub struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(weights: &[f64], width: usize, height: usize) -> Self {
+        let rows: Vec<Distribution1D> = weights
+            .chunks(width)
+            .take(height)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(rows.iter().map(Distribution1D::total).collect());
+        Self { rows, marginal }
+    }
+
+    /// Sample a point `(x, y)`, `y` selecting the row.
+    pub fn sample(&self, rng: &mut dyn RngCore) -> (f64, f64) {
+        let (y, row) = self.marginal.sample(rng);
+        let (x, _) = self.rows[row].sample(rng);
+        (x, y)
+    }
+
+    /// Density of `sample` at the point `(x, y)`.
+    pub fn pdf(&self, x: f64, y: f64) -> f64 {
+        let row = ((y * self.rows.len() as f64) as usize).min(self.rows.len() - 1);
+        let distribution = &self.rows[row];
+        let column =
+            ((x * distribution.weights.len() as f64) as usize).min(distribution.weights.len() - 1);
+        self.marginal.pdf(row) * distribution.pdf(column)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    const WIDTH: usize = 5;
+    const HEIGHT: usize = 4;
+
+    /// Uneven weights with an empty row and empty cells
+    fn weights() -> Vec<f64> {
+        vec![
+            1.0, 2.0, 0.0, 4.0, 1.0, //
+            0.0, 0.0, 0.0, 0.0, 0.0, //
+            8.0, 0.5, 3.0, 0.0, 2.0, //
+            1.0, 1.0, 6.0, 1.0, 0.25,
+        ]
+    }
+
+    fn cell(x: f64, y: f64) -> usize {
+        (y * HEIGHT as f64) as usize * WIDTH + (x * WIDTH as f64) as usize
+    }
+
+    #[test]
+    fn pdf_matches_histogram() {
+        let weights = weights();
+        let distribution = Distribution2D::new(&weights, WIDTH, HEIGHT);
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        const SAMPLES: usize = 400_000;
+        let mut histogram = [0usize; WIDTH * HEIGHT];
+        for _ in 0..SAMPLES {
+            let (x, y) = distribution.sample(&mut rng);
+            histogram[cell(x, y)] += 1;
+        }
+
+        let cell_area = 1.0 / (WIDTH * HEIGHT) as f64;
+        for (index, &count) in histogram.iter().enumerate() {
+            let x = ((index % WIDTH) as f64 + 0.5) / WIDTH as f64;
+            let y = ((index / WIDTH) as f64 + 0.5) / HEIGHT as f64;
+            let expected = distribution.pdf(x, y) * cell_area;
+            let frequency = count as f64 / SAMPLES as f64;
+            assert!(
+                (frequency - expected).abs() < 0.003,
+                "cell {}: frequency {}, expected {}",
+                index,
+                frequency,
+                expected
+            );
+            if weights[index] == 0.0 {
+                assert_eq!(count, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn sample_pdf_round_trip() {
+        let weights = weights();
+        let total: f64 = weights.iter().sum();
+        let distribution = Distribution2D::new(&weights, WIDTH, HEIGHT);
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+        for _ in 0..10_000 {
+            let (x, y) = distribution.sample(&mut rng);
+            assert!((0.0..1.0).contains(&x) && (0.0..1.0).contains(&y));
+            // The density of a cell is its share of the weight divided by its area.
+            let expected = weights[cell(x, y)] / total * (WIDTH * HEIGHT) as f64;
+            assert!(expected > 0.0);
+            assert!((distribution.pdf(x, y) - expected).abs() < 1e-12);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use crate::{
+    color::{self, Color},
+    distribution::Distribution2D,
+};
+use cgmath::{Deg, InnerSpace, Matrix, Matrix3, Vector3};
+use image::{codecs::hdr::HdrDecoder, ImageResult};
+use rand::RngCore;
+use raytracer::random_unit_vector;
+use std::{f64::consts::PI, fs::File, io::BufReader, path::Path};
+
+/// Light arriving from infinitely far away, seen by rays leaving the scene.
+pub trait Environment: Send + Sync {
+    /// Radiance arriving from the unit vector `direction`.
+    fn radiance(&self, direction: Vector3<f64>) -> Color;
+
+    /// Whether the environment is sampled like the lights of the scene. Otherwise it is only
+    /// reached by rays bouncing off surfaces.
+    fn is_light(&self) -> bool {
+        false
+    }
+
+    /// Random direction towards the environment.
+    fn random(&self, rng: &mut dyn RngCore) -> Vector3<f64> {
+        random_unit_vector(rng)
+    }
+
+    /// Solid angle pdf of `random` choosing the unit vector `direction`.
+    fn pdf_value(&self, _direction: Vector3<f64>) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+}
+
+/// The same radiance from every direction, the plain background color.
+pub struct Uniform {
+    color: Color,
+}
+
+impl Uniform {
+    pub fn new(color: Color) -> Self {
+        Self { color }
+    }
+}
+
+impl Environment for Uniform {
+    fn radiance(&self, _direction: Vector3<f64>) -> Color {
+        self.color
+    }
+}
+
+/// Equirectangular image surrounding the scene, typically a HDR photograph, seen from the
+/// inside: the top row is straight up, the center column looks along +x and the columns to
+/// its right towards +z. Directions are sampled in proportion to the luminance of the pixels.
+pub struct EnvironmentMap {
+    data: Vec<Color>,
+    width: usize,
+    height: usize,
+    /// From the space of the map to world space
+    rotation: Matrix3<f64>,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    /// Load an image in a floating point format (Radiance HDR, OpenEXR), whose values are
+    /// linear radiance. See `new` for the parameters.
+    pub fn open<P: AsRef<Path>>(path: P, rotation: f64, intensity: f64) -> ImageResult<Self> {
+        let path = path.as_ref();
+        let is_hdr = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("hdr"));
+        // `image::open` converts Radiance HDR to 8 bits, losing the range
+        let (pixels, width, height) = if is_hdr {
+            let decoder = HdrDecoder::new(BufReader::new(File::open(path)?))?;
+            let metadata = decoder.metadata();
+            let pixels = decoder.read_image_hdr()?;
+            (pixels, metadata.width, metadata.height)
+        } else {
+            let image = image::open(path)?.into_rgb32f();
+            let (width, height) = image.dimensions();
+            (image.pixels().copied().collect(), width, height)
+        };
+        let data = pixels
+            .iter()
+            .map(|pixel| Color::new(pixel[0] as f64, pixel[1] as f64, pixel[2] as f64) * intensity)
+            .collect();
+        Ok(Self::new(data, width as usize, height as usize, rotation))
+    }
+
+    /// `data` holds the pixels row by row, from the top. The map is turned by `rotation`
+    /// degrees around the y axis.
+    pub fn new(data: Vec<Color>, width: usize, height: usize, rotation: f64) -> Self {
+        // Rows towards the poles cover less solid angle
+        let weights: Vec<f64> = data
+            .iter()
+            .enumerate()
+            .map(|(i, &pixel)| {
+                let theta = PI * ((i / width) as f64 + 0.5) / height as f64;
+                color::luminance(pixel) * theta.sin()
+            })
+            .collect();
+        Self {
+            distribution: Distribution2D::new(&weights, width, height),
+            data,
+            width,
+            height,
+            rotation: Matrix3::from_angle_y(Deg(rotation)),
+        }
+    }
+
+    /// Image coordinates in [0, 1]² of a direction in map space
+    fn coordinates(direction: Vector3<f64>) -> (f64, f64) {
+        let x = (direction.z.atan2(direction.x) + PI) / (2.0 * PI);
+        let y = direction.y.clamp(-1.0, 1.0).acos() / PI;
+        (x, y)
+    }
+}
+
+impl Environment for EnvironmentMap {
+    fn radiance(&self, direction: Vector3<f64>) -> Color {
+        let (x, y) = Self::coordinates(self.rotation.transpose() * direction);
+        let i = ((x * self.width as f64) as usize).min(self.width - 1);
+        let j = ((y * self.height as f64) as usize).min(self.height - 1);
+        self.data[j * self.width + i]
+    }
+
+    fn is_light(&self) -> bool {
+        true
+    }
+
+    fn random(&self, rng: &mut dyn RngCore) -> Vector3<f64> {
+        let (x, y) = self.distribution.sample(rng);
+        let phi = 2.0 * PI * x - PI;
+        let theta = PI * y;
+        let direction = Vector3::new(
+            theta.sin() * phi.cos(),
+            theta.cos(),
+            theta.sin() * phi.sin(),
+        );
+        self.rotation * direction
+    }
+
+    fn pdf_value(&self, direction: Vector3<f64>) -> f64 {
+        let (x, y) = Self::coordinates(self.rotation.transpose() * direction.normalize());
+        let sin_theta = (PI * y).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        // The image spans 2 pi by pi radians
+        self.distribution.pdf(x, y) / (2.0 * PI * PI * sin_theta)
+    }
+}
@@ -1,6 +1,7 @@
 use crate::{aabb::Aabb, material::Material, ray::*};
-use cgmath::{InnerSpace, Point3, Vector3};
-use std::option::Option;
+use cgmath::{InnerSpace, Matrix4, Point3, SquareMatrix, Transform as _, Vector3};
+use rand::RngCore;
+use std::{option::Option, sync::Arc};
 
 pub struct HitRecord<'a> {
     pub p: Point3<f64>,
@@ -24,6 +25,122 @@ impl<'a> HitRecord<'a> {
 }
 
 pub trait Hittable: Send + Sync {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>>;
     fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb>;
+
+    /// Solid angle pdf of `random` choosing `direction` as seen from `origin`.
+    fn pdf_value(&self, _origin: Point3<f64>, _direction: Vector3<f64>) -> f64 {
+        0.0
+    }
+
+    /// Random direction from `origin` towards this object.
+    fn random(&self, _origin: Point3<f64>, _rng: &mut dyn RngCore) -> Vector3<f64> {
+        Vector3::new(1.0, 0.0, 0.0)
+    }
+
+    /// Collect all emissive objects which can be sampled by `pdf_value` and `random`.
+    fn lights<'a>(&'a self, _lights: &mut Vec<Emitter<'a>>) {}
+}
+
+/// An emissive object collected by `Hittable::lights`, placed by the transformations around
+/// it. These are composed once while collecting, so sampling does not walk the scene again.
+#[derive(Clone, Copy)]
+pub struct Emitter<'a> {
+    object: &'a dyn Hittable,
+    /// Matrix from the space of `object` into world space and its inverse, `None` if they
+    /// are the same
+    placement: Option<(Matrix4<f64>, Matrix4<f64>)>,
+}
+
+impl<'a> Emitter<'a> {
+    pub fn new(object: &'a dyn Hittable) -> Self {
+        Self {
+            object,
+            placement: None,
+        }
+    }
+
+    /// Place the emitter by `matrix`, with the given inverse, after its current placement.
+    pub fn placed(self, matrix: &Matrix4<f64>, inverse: &Matrix4<f64>) -> Self {
+        let placement = match self.placement {
+            Some((inner, inner_inverse)) => (matrix * inner, inner_inverse * inverse),
+            None => (*matrix, *inverse),
+        };
+        Self {
+            placement: Some(placement),
+            ..self
+        }
+    }
+
+    /// Solid angle pdf of `random` choosing `direction` as seen from `origin`.
+    pub fn pdf_value(&self, origin: Point3<f64>, direction: Vector3<f64>) -> f64 {
+        match &self.placement {
+            Some((_, inverse)) => {
+                let local_direction = inverse.transform_vector(direction);
+                let local_pdf = self
+                    .object
+                    .pdf_value(inverse.transform_point(origin), local_direction);
+                // A linear map A takes the unit direction w to A w / |A w|, which changes
+                // solid angles by |det A| / |A w|^3.
+                let stretch = direction.magnitude() / local_direction.magnitude();
+                local_pdf * inverse.determinant().abs() * stretch.powi(3)
+            }
+            None => self.object.pdf_value(origin, direction),
+        }
+    }
+
+    /// Random direction from `origin` towards the emitter.
+    pub fn random(&self, origin: Point3<f64>, rng: &mut dyn RngCore) -> Vector3<f64> {
+        match &self.placement {
+            Some((matrix, inverse)) => {
+                matrix.transform_vector(self.object.random(inverse.transform_point(origin), rng))
+            }
+            None => self.object.random(origin, rng),
+        }
+    }
+}
+
+impl Hittable for Box<dyn Hittable> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.as_ref().hit(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.as_ref().bounding_box(time0, time1)
+    }
+
+    fn pdf_value(&self, origin: Point3<f64>, direction: Vector3<f64>) -> f64 {
+        self.as_ref().pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: Point3<f64>, rng: &mut dyn RngCore) -> Vector3<f64> {
+        self.as_ref().random(origin, rng)
+    }
+
+    fn lights<'a>(&'a self, lights: &mut Vec<Emitter<'a>>) {
+        self.as_ref().lights(lights)
+    }
+}
+
+/// Shared geometry, e.g. the bottom-level `Bvh` of an `Instance`
+impl<H: Hittable + ?Sized> Hittable for Arc<H> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.as_ref().hit(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.as_ref().bounding_box(time0, time1)
+    }
+
+    fn pdf_value(&self, origin: Point3<f64>, direction: Vector3<f64>) -> f64 {
+        self.as_ref().pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: Point3<f64>, rng: &mut dyn RngCore) -> Vector3<f64> {
+        self.as_ref().random(origin, rng)
+    }
+
+    fn lights<'a>(&'a self, lights: &mut Vec<Emitter<'a>>) {
+        self.as_ref().lights(lights)
+    }
 }
@@ -1,6 +1,6 @@
 use crate::{
     aabb::Aabb,
-    hitable::{HitRecord, Hittable},
+    hitable::{Emitter, HitRecord, Hittable},
     ray::*,
 };
 use std::option::Option;
@@ -19,7 +19,7 @@ impl HitableList {
     }
 }
 impl Hittable for HitableList {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let mut hit_anything = None;
         let mut closest_so_far = t_max;
 
@@ -45,4 +45,10 @@ impl Hittable for HitableList {
             _ => None,
         }
     }
+
+    fn lights<'a>(&'a self, lights: &mut Vec<Emitter<'a>>) {
+        for obj in self.objects.iter() {
+            obj.lights(lights);
+        }
+    }
 }
@@ -0,0 +1,83 @@
+use crate::{aabb::Aabb, hitable::*, material::Material, ray::Ray, transform::Transform};
+use cgmath::Matrix4;
+use std::sync::Arc;
+
+/// Placement of shared geometry. Many instances can reference the same geometry, usually a
+/// bottom-level `Bvh`, each adding nothing but a transformation and an optional material
+/// replacing the materials of the geometry.
+pub struct Instance {
+    geometry: Transform<Arc<dyn Hittable>>,
+    material: Option<Arc<dyn Material>>,
+}
+
+impl Instance {
+    /// Panics if `matrix` is not invertible.
+    pub fn new(geometry: Arc<dyn Hittable>, matrix: Matrix4<f64>) -> Self {
+        Self {
+            geometry: Transform::with_matrix(geometry, matrix),
+            material: None,
+        }
+    }
+
+    pub fn with_material(self, material: Arc<dyn Material>) -> Self {
+        Self {
+            material: Some(material),
+            ..self
+        }
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.geometry.hit(ray, t_min, t_max).map(|mut record| {
+            if let Some(material) = &self.material {
+                record.material = material.as_ref();
+            }
+            record
+        })
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.geometry.bounding_box(time0, time1)
+    }
+
+    /// Only the geometry's own materials tell which parts of it emit, so an instance with a
+    /// replaced material does not sample its lights.
+    fn lights<'a>(&'a self, lights: &mut Vec<Emitter<'a>>) {
+        if self.material.is_none() {
+            self.geometry.lights(lights);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        color::Color,
+        material::{DiffuseLight, Lambertian},
+        sphere::Sphere,
+        texture::SolidColor,
+    };
+    use cgmath::{Point3, Vector3};
+
+    #[test]
+    fn lights_unless_material_replaced() {
+        let light = Arc::new(DiffuseLight::with_color(&Color::new(1.0, 1.0, 1.0)));
+        let geometry: Arc<dyn Hittable> =
+            Arc::new(Sphere::new(Point3::new(0.0, 0.0, 0.0), 1.0, light));
+        let matrix = Matrix4::from_translation(Vector3::new(0.0, 2.0, 0.0));
+
+        let instance = Instance::new(geometry.clone(), matrix);
+        let mut lights = vec![];
+        instance.lights(&mut lights);
+        assert_eq!(lights.len(), 1);
+
+        let white = Arc::new(SolidColor::new(&Color::new(1.0, 1.0, 1.0)));
+        let instance =
+            Instance::new(geometry, matrix).with_material(Arc::new(Lambertian::new(white)));
+        let mut lights = vec![];
+        instance.lights(&mut lights);
+        assert!(lights.is_empty());
+    }
+}
@@ -0,0 +1,160 @@
+use crate::{
+    color::{self, Color},
+    environment::Environment,
+    hitable::{HitRecord, Hittable},
+    light::{LightSample, Lights},
+    onb::Onb,
+    ray::Ray,
+};
+use cgmath::{InnerSpace, Vector3};
+use rand::{Rng, RngCore};
+
+/// Unidirectional path tracer with next event estimation. Paths are extended in a loop,
+/// carrying the product of all attenuations so far (the throughput). After `rr_depth`
+/// bounces, paths are terminated randomly with a probability that grows as their throughput
+/// drops (Russian roulette), and survivors are reweighted to keep the estimate unbiased.
+pub struct PathTracer<'a> {
+    pub environment: &'a dyn Environment,
+    pub world: &'a dyn Hittable,
+    pub lights: &'a Lights<'a>,
+    /// Maximum number of path vertices
+    pub max_depth: usize,
+    /// Number of bounces before Russian roulette starts
+    pub rr_depth: usize,
+}
+
+impl<'a> PathTracer<'a> {
+    /// Radiance arriving along `ray`.
+    pub fn color(&self, ray: Ray, rng: &mut dyn RngCore) -> Color {
+        // With unit directions, the hit distance `t` is the length travelled.
+        let mut ray = Ray::new(ray.origin(), ray.direction().normalize(), ray.time());
+        let mut color = color::black();
+        let mut throughput = color::white();
+        // The pdf with which the previous bounce chose `ray`, `None` for camera rays and
+        // specular bounces.
+        let mut scattering_pdf: Option<f64> = None;
+
+        for depth in 0..self.max_depth {
+            // If the ray hits nothing, add the light of the environment
+            let record = match self.world.hit(&ray, 0.001, f64::MAX) {
+                None => {
+                    let mut radiance = self.environment.radiance(ray.direction());
+                    if let Some(pdf) = scattering_pdf {
+                        if self.environment.is_light() {
+                            let light_pdf = self.lights.pdf_value(ray.origin(), ray.direction());
+                            radiance *= power_heuristic(pdf, light_pdf);
+                        }
+                    }
+                    color += throughput.zip(radiance, |l, r| l * r);
+                    break;
+                }
+                Some(record) => record,
+            };
+
+            let mut emitted = record.material.emitted(record.u, record.v, &record.p);
+            // A light hit by a sampled bounce could also have been reached by light
+            // sampling at the previous vertex, so it only counts with its MIS weight.
+            if let Some(pdf) = scattering_pdf {
+                if record.material.is_emissive() {
+                    let light_pdf = self.lights.pdf_value(ray.origin(), ray.direction());
+                    emitted *= power_heuristic(pdf, light_pdf);
+                }
+            }
+            color += throughput.zip(emitted, |l, r| l * r);
+
+            let frame = Onb::from_w(record.normal);
+            let wo = frame.to_basis(-ray.direction());
+            let sample = match record.material.sample(&record, wo, rng) {
+                None => break,
+                Some(sample) => sample,
+            };
+            let specular = sample.flags.is_specular();
+            if !specular {
+                let direct = self.sample_lights(&ray, &record, &frame, wo, rng);
+                color += throughput.zip(direct, |l, r| l * r);
+            }
+            throughput = throughput.zip(sample.value, |l, r| l * r);
+
+            if depth + 1 >= self.rr_depth {
+                let max = throughput.x.max(throughput.y).max(throughput.z);
+                let termination = (1.0 - max).max(0.05);
+                if rng.gen_range(0.0..1.0) < termination {
+                    break;
+                }
+                throughput /= 1.0 - termination;
+            }
+
+            scattering_pdf = if specular { None } else { Some(sample.pdf) };
+            ray = Ray::new(record.p, frame.local(sample.wi), ray.time());
+        }
+
+        color
+    }
+
+    /// Direct light arriving at `record` from a randomly chosen light, weighted against
+    /// finding the same light by sampling the material.
+    fn sample_lights(
+        &self,
+        ray: &Ray,
+        record: &HitRecord,
+        frame: &Onb,
+        wo: Vector3<f64>,
+        rng: &mut dyn RngCore,
+    ) -> Color {
+        if self.lights.is_empty() {
+            return color::black();
+        }
+        let (direction, light_pdf) = match self.lights.sample(record.p, rng) {
+            LightSample::Direction { direction, pdf } => (direction, pdf),
+            LightSample::Punctual {
+                direction,
+                distance,
+                irradiance,
+            } => {
+                // Nothing else samples punctual lights, so there is nothing to weight against
+                let f = record.material.eval(record, wo, frame.to_basis(direction));
+                let shadow_ray = Ray::new(record.p, direction, ray.time());
+                if f == color::black()
+                    || self
+                        .world
+                        .hit(&shadow_ray, 0.001, distance - 0.001)
+                        .is_some()
+                {
+                    return color::black();
+                }
+                return f.zip(irradiance, |f, e| f * e);
+            }
+        };
+        if light_pdf <= 0.0 {
+            return color::black();
+        }
+        let wi = frame.to_basis(direction.normalize());
+        let f = record.material.eval(record, wo, wi);
+        if f == color::black() {
+            return color::black();
+        }
+
+        let shadow_ray = Ray::new(record.p, direction, ray.time());
+        let emitted = match self.world.hit(&shadow_ray, 0.001, f64::MAX) {
+            Some(light) if light.material.is_emissive() => {
+                light.material.emitted(light.u, light.v, &light.p)
+            }
+            Some(_) => return color::black(),
+            None => self.environment.radiance(direction.normalize()),
+        };
+        let scattering_pdf = record.material.pdf(record, wo, wi);
+        let weight = power_heuristic(light_pdf, scattering_pdf);
+        f.zip(emitted, |f, e| f * e) * weight / light_pdf
+    }
+}
+
+/// Multiple importance sampling weight of a strategy with pdf `f` against one with pdf `g`.
+fn power_heuristic(f: f64, g: f64) -> f64 {
+    let f2 = f * f;
+    let g2 = g * g;
+    if f2 + g2 > 0.0 {
+        f2 / (f2 + g2)
+    } else {
+        0.0
+    }
+}
@@ -0,0 +1,193 @@
+use crate::{
+    color::Color,
+    environment::Environment,
+    hitable::{Emitter, Hittable},
+};
+use cgmath::{InnerSpace, Point3, Vector3};
+use rand::{Rng, RngCore};
+
+/// Light emitted from a single point or along a single direction. No ray can hit it, so it
+/// only contributes through shadow rays.
+pub trait PunctualLight: Send + Sync {
+    /// Unit vector from `p` towards the light, the distance to it, and the irradiance it
+    /// delivers at `p` on a surface facing it.
+    fn illuminate(&self, p: Point3<f64>) -> (Vector3<f64>, f64, Color);
+}
+
+/// Light radiating equally in all directions from `position`. `intensity` is the
+/// irradiance at a distance of 1.
+pub struct PointLight {
+    position: Point3<f64>,
+    intensity: Color,
+}
+
+impl PointLight {
+    pub fn new(position: Point3<f64>, intensity: Color) -> Self {
+        Self {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl PunctualLight for PointLight {
+    fn illuminate(&self, p: Point3<f64>) -> (Vector3<f64>, f64, Color) {
+        let offset = self.position - p;
+        let distance2 = offset.magnitude2();
+        let distance = distance2.sqrt();
+        (offset / distance, distance, self.intensity / distance2)
+    }
+}
+
+/// Point light restricted to a cone around `direction`. The intensity is full within
+/// `inner_angle` of the axis and falls off smoothly to zero at `angle`.
+pub struct SpotLight {
+    position: Point3<f64>,
+    direction: Vector3<f64>,
+    intensity: Color,
+    cos_inner: f64,
+    cos_outer: f64,
+}
+
+impl SpotLight {
+    /// Angles are in degrees, measured from the axis of the cone.
+    pub fn new(
+        position: Point3<f64>,
+        direction: Vector3<f64>,
+        intensity: Color,
+        angle: f64,
+        inner_angle: f64,
+    ) -> Self {
+        Self {
+            position,
+            direction: direction.normalize(),
+            intensity,
+            cos_inner: inner_angle.min(angle).to_radians().cos(),
+            cos_outer: angle.to_radians().cos(),
+        }
+    }
+
+    fn falloff(&self, cos_theta: f64) -> f64 {
+        if cos_theta >= self.cos_inner {
+            return 1.0;
+        }
+        let t = ((cos_theta - self.cos_outer) / (self.cos_inner - self.cos_outer)).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+impl PunctualLight for SpotLight {
+    fn illuminate(&self, p: Point3<f64>) -> (Vector3<f64>, f64, Color) {
+        let offset = self.position - p;
+        let distance2 = offset.magnitude2();
+        let distance = distance2.sqrt();
+        let direction = offset / distance;
+        let falloff = self.falloff(-direction.dot(self.direction));
+        (direction, distance, self.intensity * (falloff / distance2))
+    }
+}
+
+/// Parallel light travelling along `direction` from infinitely far away, like sunlight.
+pub struct DirectionalLight {
+    direction: Vector3<f64>,
+    irradiance: Color,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vector3<f64>, irradiance: Color) -> Self {
+        Self {
+            direction: direction.normalize(),
+            irradiance,
+        }
+    }
+}
+
+impl PunctualLight for DirectionalLight {
+    fn illuminate(&self, _p: Point3<f64>) -> (Vector3<f64>, f64, Color) {
+        (-self.direction, f64::INFINITY, self.irradiance)
+    }
+}
+
+/// A light chosen by `Lights::sample`.
+pub enum LightSample {
+    /// Direction towards an emissive object or the environment, with its solid angle pdf.
+    Direction { direction: Vector3<f64>, pdf: f64 },
+    /// Unit vector towards a punctual light, its distance and its irradiance divided by the
+    /// probability of choosing it.
+    Punctual {
+        direction: Vector3<f64>,
+        distance: f64,
+        irradiance: Color,
+    },
+}
+
+/// Emissive objects of a scene, the punctual lights, and the environment if it is a light,
+/// sampled directly at every diffuse bounce (next event estimation). Each light is chosen
+/// with the same probability.
+pub struct Lights<'a> {
+    objects: Vec<Emitter<'a>>,
+    punctual: &'a [Box<dyn PunctualLight>],
+    environment: Option<&'a dyn Environment>,
+}
+
+impl<'a> Lights<'a> {
+    pub fn new(
+        world: &'a dyn Hittable,
+        punctual: &'a [Box<dyn PunctualLight>],
+        environment: &'a dyn Environment,
+    ) -> Self {
+        let mut objects = vec![];
+        world.lights(&mut objects);
+        Self {
+            objects,
+            punctual,
+            environment: environment.is_light().then_some(environment),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.objects.len() + self.punctual.len() + usize::from(self.environment.is_some())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Solid angle pdf of `sample` choosing `direction` as seen from `origin`. Punctual
+    /// lights cannot be found by a direction and do not count.
+    pub fn pdf_value(&self, origin: Point3<f64>, direction: Vector3<f64>) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let objects: f64 = self
+            .objects
+            .iter()
+            .map(|light| light.pdf_value(origin, direction))
+            .sum();
+        let environment = self.environment.map_or(0.0, |environment| {
+            environment.pdf_value(direction.normalize())
+        });
+        (objects + environment) / self.len() as f64
+    }
+
+    /// Light reaching `origin` from one of the lights.
+    pub fn sample(&self, origin: Point3<f64>, rng: &mut dyn RngCore) -> LightSample {
+        let index = rng.gen_range(0..self.len());
+        let direction = if let Some(light) = self.objects.get(index) {
+            light.random(origin, rng)
+        } else if let Some(light) = self.punctual.get(index - self.objects.len()) {
+            let (direction, distance, irradiance) = light.illuminate(origin);
+            return LightSample::Punctual {
+                direction,
+                distance,
+                irradiance: irradiance * self.len() as f64,
+            };
+        } else {
+            self.environment.unwrap().random(rng)
+        };
+        LightSample::Direction {
+            direction,
+            pdf: self.pdf_value(origin, direction),
+        }
+    }
+}
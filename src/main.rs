@@ -1,31 +1,53 @@
-#[cfg(test)]
-#[macro_use]
-extern crate approx;
-
-use crate::{camera::Camera, color::*, world::*};
-use cgmath::{Point3, Vector3};
+use crate::{
+    bvh::{Bvh, BvhSplit},
+    camera::Camera,
+    color::*,
+    environment::Uniform,
+    hitable::Hittable,
+    integrator::PathTracer,
+    light::Lights,
+    scene::{CameraSettings, Scene},
+    sky::Sky,
+    tonemap::{Operator, ToneMapping},
+    world::*,
+};
+use cgmath::Vector3;
 use clap::{arg, command};
-use image::{load_from_memory_with_format, ImageFormat};
 use indicatif::{ProgressBar, ProgressStyle};
 use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
 use rayon::prelude::*;
+use std::{ops::Range, path::Path};
 
 mod aabb;
 mod aarect;
+mod animation;
 mod bvh;
 mod camera;
 mod color;
 mod constant_medium;
 mod cuboid;
+mod distribution;
+mod environment;
 mod hitable;
 mod hitable_list;
+mod instance;
+mod integrator;
+mod light;
 mod material;
+mod microfacet;
+mod obj;
+mod onb;
+mod output;
 mod perlin;
 mod ray;
-mod rotate;
+mod scene;
+mod sky;
 mod sphere;
 mod texture;
-mod translate;
+mod tonemap;
+mod transform;
+mod triangle;
 mod world;
 
 fn main() {
@@ -73,6 +95,145 @@ fn main() {
             .default_value("0")
             .validator(|s| s.parse::<usize>())
         )
+        .arg(
+            arg!(
+                --scene <PATH> "Load scene description file instead of a built-in world"
+            )
+            .required(false)
+        )
+        .arg(
+            arg!(
+                --bvh <SPLIT> "BVH construction strategy for the scene objects"
+            )
+            .required(false)
+            .default_value("sah")
+            .possible_values(["median", "sah"])
+        )
+        .arg(
+            arg!(
+                --"sah-bins" <BINS> "number of bins evaluated per SAH split"
+            )
+            .required(false)
+            .default_value("12")
+            .validator(|s| s.parse::<usize>())
+        )
+        .arg(
+            arg!(
+                --"bvh-leaf-size" <SIZE> "maximum number of objects per SAH leaf"
+            )
+            .required(false)
+            .default_value("4")
+            .validator(|s| s.parse::<usize>())
+        )
+        .arg(
+            arg!(
+                --"max-depth" <DEPTH> "maximum number of bounces per path"
+            )
+            .required(false)
+            .default_value("50")
+            .validator(|s| s.parse::<usize>())
+        )
+        .arg(
+            arg!(
+                --"rr-depth" <DEPTH> "number of bounces before paths are terminated by Russian roulette"
+            )
+            .required(false)
+            .default_value("5")
+            .validator(|s| s.parse::<usize>())
+        )
+        .arg(
+            arg!(
+                -o --output <PATH> "output image, .exr and .hdr store linear floating-point radiance"
+            )
+            .required(false)
+            .default_value("raytracer.png")
+        )
+        .arg(
+            arg!(
+                --exposure <STOPS> "exposure adjustment in stops before tone mapping"
+            )
+            .required(false)
+            .default_value("0")
+            .allow_hyphen_values(true)
+            .validator(|s| s.parse::<f64>())
+        )
+        .arg(
+            arg!(
+                --tonemap <OPERATOR> "tone mapping operator for 8-bit output"
+            )
+            .required(false)
+            .default_value("clamp")
+            .possible_values(["clamp", "reinhard", "reinhard-extended", "aces", "hable"])
+        )
+        .arg(
+            arg!(
+                --"white-point" <RADIANCE> "smallest radiance mapped to white by reinhard-extended"
+            )
+            .required(false)
+            .default_value("4")
+            .validator(|s| s.parse::<f64>())
+        )
+        .arg(
+            arg!(
+                --sky "replace the background by a daylight sky and sun"
+            )
+        )
+        .arg(
+            arg!(
+                --"sun-elevation" <DEGREES> "angle of the sun above the horizon"
+            )
+            .required(false)
+            .default_value("45")
+            .allow_hyphen_values(true)
+            .validator(|s| s.parse::<f64>())
+        )
+        .arg(
+            arg!(
+                --"sun-azimuth" <DEGREES> "direction of the sun, turning from -z (0) towards +x (90)"
+            )
+            .required(false)
+            .default_value("0")
+            .allow_hyphen_values(true)
+            .validator(|s| s.parse::<f64>())
+        )
+        .arg(
+            arg!(
+                --turbidity <TURBIDITY> "haze of the sky, from 2 (clear) to 10 (hazy)"
+            )
+            .required(false)
+            .default_value("3")
+            .validator(|s| s.parse::<f64>())
+        )
+        .arg(
+            arg!(
+                --frames <RANGE> "render the frames start..end (end excluded) of an animation to numbered files, or to one .gif or .apng"
+            )
+            .required(false)
+            .validator(parse_frames)
+        )
+        .arg(
+            arg!(
+                --fps <FPS> "frames per second of an animation"
+            )
+            .required(false)
+            .default_value("24")
+            .validator(parse_fps)
+        )
+        .arg(
+            arg!(
+                --shutter <FRACTION> "fraction of each frame of an animation the shutter is open"
+            )
+            .required(false)
+            .default_value("0.5")
+            .validator(parse_shutter)
+        )
+        .arg(
+            arg!(
+                --seed <SEED> "seed for scene generation and sampling, random by default. Identical seeds render identical images"
+            )
+            .required(false)
+            .validator(|s| s.parse::<u64>())
+        )
         .get_matches();
 
     let threads: usize = matches.value_of_t("threads").unwrap();
@@ -83,237 +244,204 @@ fn main() {
         .build_global()
         .unwrap();
 
-    let mut rng = thread_rng();
+    let seed = match matches.value_of("seed") {
+        Some(seed) => seed.parse().unwrap(),
+        None => thread_rng().gen(),
+    };
+    println!("Seed: {}", seed);
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
 
     // Image
     let image_width: usize = matches.value_of_t("image-width").unwrap();
     let image_height: usize = matches.value_of_t("image-height").unwrap();
     let ascpect_ratio = image_width as f64 / image_height as f64;
-    const MAX_DEPTH: usize = 50;
 
-    // World
-    let (world, lookfrom, lookat, vfov, aperture, background) =
-        match matches.value_of_t("world").unwrap() {
-            1 => {
-                let lookfrom = Point3 {
-                    x: 13.0,
-                    y: 2.0,
-                    z: 3.0,
-                };
-                let lookat = Point3 {
-                    x: 0.0,
-                    y: 0.0,
-                    z: 0.0,
-                };
-                let vfov = 20.0;
-                let aperture = 0.0;
-                let background = Color::new(0.70, 0.80, 1.00);
+    // Timeline: the shutter of the camera, or one window per frame of an animation
+    let frames = matches
+        .value_of("frames")
+        .map(|range| parse_frames(range).unwrap());
+    let fps: f64 = matches.value_of_t("fps").unwrap();
+    let shutter: f64 = matches.value_of_t("shutter").unwrap();
+    let frame_window = |frame: usize| {
+        let open = frame as f64 / fps;
+        (frame, open, open + shutter / fps)
+    };
+    // Everything is built for the whole animation
+    let timeline = frames
+        .clone()
+        .map(|frames| (frame_window(frames.start).1, frame_window(frames.end - 1).2));
 
-                (
-                    two_spheres(&mut rng),
-                    lookfrom,
-                    lookat,
-                    vfov,
-                    aperture,
-                    background,
-                )
-            }
-            2 => {
-                let lookfrom = Point3 {
-                    x: 13.0,
-                    y: 2.0,
-                    z: 3.0,
-                };
-                let lookat = Point3 {
-                    x: 0.0,
-                    y: 0.0,
-                    z: 0.0,
-                };
-                let vfov = 20.0;
-                let aperture = 0.0;
-                let background = Color::new(0.70, 0.80, 1.00);
+    // Strategy for the top-level BVH and the bvh objects of a scene file
+    let split = match matches.value_of("bvh").unwrap() {
+        "median" => BvhSplit::Median,
+        _ => BvhSplit::Sah {
+            bins: matches.value_of_t("sah-bins").unwrap(),
+            max_leaf_size: matches.value_of_t("bvh-leaf-size").unwrap(),
+        },
+    };
 
-                (
-                    two_perlin_spheres(&mut rng),
-                    lookfrom,
-                    lookat,
-                    vfov,
-                    aperture,
-                    background,
-                )
-            }
-            3 => {
-                let lookfrom = Point3 {
-                    x: 13.0,
-                    y: 2.0,
-                    z: 3.0,
-                };
-                let lookat = Point3 {
-                    x: 0.0,
-                    y: 0.0,
-                    z: 0.0,
-                };
-                let vfov = 20.0;
-                let aperture = 0.0;
-                let background = Color::new(0.70, 0.80, 1.00);
+    // World
+    let mut scene = match matches.value_of("scene") {
+        Some(path) => scene::load(path, timeline, split, &mut rng).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }),
+        None => builtin_scene(matches.value_of_t("world").unwrap(), &mut rng),
+    };
+    if matches.is_present("sky") {
+        let elevation = matches
+            .value_of_t::<f64>("sun-elevation")
+            .unwrap()
+            .to_radians();
+        let azimuth = matches
+            .value_of_t::<f64>("sun-azimuth")
+            .unwrap()
+            .to_radians();
+        let sun = Vector3::new(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            -elevation.cos() * azimuth.cos(),
+        );
+        scene.environment = Box::new(Sky::new(sun, matches.value_of_t("turbidity").unwrap(), 1.0));
+    }
 
-                (
-                    earth(&mut rng),
-                    lookfrom,
-                    lookat,
-                    vfov,
-                    aperture,
-                    background,
-                )
-            }
-            4 => {
-                // Run with -s 400
-                let lookfrom = Point3 {
-                    x: 26.0,
-                    y: 3.0,
-                    z: 6.0,
-                };
-                let lookat = Point3 {
-                    x: 0.0,
-                    y: 2.0,
-                    z: 0.0,
-                };
-                let vfov = 20.0;
-                let aperture = 0.0;
-                let background = color::black();
+    let windows: Vec<(usize, f64, f64)> = match &frames {
+        Some(frames) => frames.clone().map(frame_window).collect(),
+        None => vec![(0, scene.camera.time0, scene.camera.time1)],
+    };
+    let time0 = windows.first().map_or(0.0, |window| window.1);
+    let time1 = windows.last().map_or(0.0, |window| window.2);
 
-                (
-                    world::simple_light(&mut rng),
-                    lookfrom,
-                    lookat,
-                    vfov,
-                    aperture,
-                    background,
-                )
-            }
-            5 => {
-                // Run with -s 200 -x 600 -y 600
-                let lookfrom = Point3 {
-                    x: 278.0,
-                    y: 278.0,
-                    z: -800.0,
-                };
-                let lookat = Point3 {
-                    x: 278.0,
-                    y: 278.0,
-                    z: 0.0,
-                };
-                let vfov = 40.0;
-                let aperture = 0.0;
-                let background = color::black();
+    // Acceleration structure
+    let world: Box<dyn Hittable> = if scene.world.objects.is_empty() {
+        Box::new(scene.world)
+    } else {
+        let bvh = Bvh::with_split(scene.world.objects, time0, time1, split);
+        println!("BVH cost: {:.2}", bvh.cost());
+        Box::new(bvh)
+    };
 
-                (
-                    world::cornell_box(&mut rng),
-                    lookfrom,
-                    lookat,
-                    vfov,
-                    aperture,
-                    background,
-                )
-            }
-            6 => {
-                // Run with -s 200 -x 600 -y 600
-                let lookfrom = Point3 {
-                    x: 278.0,
-                    y: 278.0,
-                    z: -800.0,
-                };
-                let lookat = Point3 {
-                    x: 278.0,
-                    y: 278.0,
-                    z: 0.0,
-                };
-                let vfov = 40.0;
-                let aperture = 0.0;
-                let background = color::black();
+    let world_ref = world.as_ref();
+    let lights = Lights::new(world_ref, &scene.lights, scene.environment.as_ref());
+    let integrator = PathTracer {
+        environment: scene.environment.as_ref(),
+        world: world_ref,
+        lights: &lights,
+        max_depth: matches.value_of_t("max-depth").unwrap(),
+        rr_depth: matches.value_of_t("rr-depth").unwrap(),
+    };
+    let tone_mapping = ToneMapping {
+        exposure: matches.value_of_t("exposure").unwrap(),
+        operator: match matches.value_of("tonemap").unwrap() {
+            "reinhard" => Operator::Reinhard,
+            "reinhard-extended" => Operator::ExtendedReinhard {
+                white: matches.value_of_t("white-point").unwrap(),
+            },
+            "aces" => Operator::Aces,
+            "hable" => Operator::Hable,
+            _ => Operator::Clamp,
+        },
+    };
+    let output = Path::new(matches.value_of("output").unwrap());
+    let exit = |path: &Path, err: image::ImageError| -> ! {
+        eprintln!("{}: {}", path.display(), err);
+        std::process::exit(1);
+    };
 
-                (
-                    world::cornell_smoke(&mut rng),
-                    lookfrom,
-                    lookat,
-                    vfov,
-                    aperture,
-                    background,
-                )
-            }
-            7 => {
-                // Run with -s 10000 -x 800 -y 800
-                let lookfrom = Point3 {
-                    x: 478.0,
-                    y: 278.0,
-                    z: -600.0,
-                };
-                let lookat = Point3 {
-                    x: 278.0,
-                    y: 278.0,
-                    z: 0.0,
-                };
-                let vfov = 40.0;
-                let aperture = 0.0;
-                let background = color::black();
+    // Render
+    let mut animation = vec![];
+    for (frame, open, close) in windows {
+        // The camera is placed once per frame, in the middle of the shutter window
+        let settings = CameraSettings {
+            time0: open,
+            time1: close,
+            ..scene.camera.at((open + close) / 2.0)
+        };
+        let camera = settings
+            .camera(ascpect_ratio, world_ref)
+            .unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            });
+        let image = render(
+            camera.as_ref(),
+            &integrator,
+            image_width,
+            image_height,
+            samples_per_pixel,
+            seed,
+            frame,
+        );
 
-                (
-                    world::final_scene(&mut rng),
-                    lookfrom,
-                    lookat,
-                    vfov,
-                    aperture,
-                    background,
-                )
+        if frames.is_none() {
+            if let Err(err) = output::save(output, &image, image_width, image_height, &tone_mapping)
+            {
+                exit(output, err);
             }
-            _ => {
-                let lookfrom = Point3 {
-                    x: 13.0,
-                    y: 2.0,
-                    z: 3.0,
-                };
-                let lookat = Point3 {
-                    x: 0.0,
-                    y: 0.0,
-                    z: 0.0,
-                };
-                let vfov = 20.0;
-                let aperture = 0.1;
-                let background = Color::new(0.70, 0.80, 1.00);
-                (
-                    random_scene(&mut rng),
-                    lookfrom,
-                    lookat,
-                    vfov,
-                    aperture,
-                    background,
-                )
+        } else if output::is_animation(output) {
+            animation.push(
+                image
+                    .iter()
+                    .flat_map(|pixel| tone_mapping.encode(*pixel))
+                    .collect(),
+            );
+        } else {
+            let path = output::frame_path(output, frame);
+            if let Err(err) = output::save(&path, &image, image_width, image_height, &tone_mapping)
+            {
+                exit(&path, err);
             }
-        };
+        }
+    }
+    if !animation.is_empty() {
+        if let Err(err) = output::save_animation(output, &animation, image_width, image_height, fps)
+        {
+            exit(output, err);
+        }
+    }
+}
 
-    // Camera
-    let vup = Vector3 {
-        x: 0.0,
-        y: 1.0,
-        z: 0.0,
-    };
-    let dist_to_focus = 10.0;
+/// Parse a range of frames `start..end`, excluding `end`.
+fn parse_frames(range: &str) -> Result<Range<usize>, String> {
+    let (start, end) = range
+        .split_once("..")
+        .ok_or_else(|| "expected start..end".to_string())?;
+    let start: usize = start.trim().parse().map_err(|err| format!("{}", err))?;
+    let end: usize = end.trim().parse().map_err(|err| format!("{}", err))?;
+    if start >= end {
+        return Err("no frames in range".to_string());
+    }
+    Ok(start..end)
+}
 
-    let camera = Camera::new(
-        lookfrom,
-        lookat,
-        vup,
-        vfov,
-        ascpect_ratio,
-        aperture,
-        dist_to_focus,
-        0.0,
-        1.0,
-    );
+/// Parse a frame rate, which must be positive and finite.
+fn parse_fps(fps: &str) -> Result<f64, String> {
+    let fps: f64 = fps.parse().map_err(|err| format!("{}", err))?;
+    if !(fps.is_finite() && fps > 0.0) {
+        return Err("must be positive and finite".to_string());
+    }
+    Ok(fps)
+}
 
-    // Render
-    let camera_ref = &camera;
-    let world_ref = &world;
+/// Parse the fraction of a frame the shutter is open, from 0 to 1.
+fn parse_shutter(shutter: &str) -> Result<f64, String> {
+    let shutter: f64 = shutter.parse().map_err(|err| format!("{}", err))?;
+    if !(0.0..=1.0).contains(&shutter) {
+        return Err("must be between 0 and 1".to_string());
+    }
+    Ok(shutter)
+}
+
+/// Average radiance of the pixels of `frame` seen by `camera`, row by row from the top.
+fn render(
+    camera: &dyn Camera,
+    integrator: &PathTracer,
+    image_width: usize,
+    image_height: usize,
+    samples_per_pixel: usize,
+    seed: u64,
+    frame: usize,
+) -> Vec<Color> {
     let bar = &Box::new(ProgressBar::new((image_width * image_height) as u64));
     bar.set_prefix("   Rendering");
     bar.set_style(
@@ -327,13 +455,17 @@ fn main() {
         .rev()
         .flat_map(|y| {
             (0..image_width).into_par_iter().map(move |x| {
+                // Every pixel of every frame draws from its own stream, independent of the
+                // thread it is rendered on. Stream 0 is used by the scene generation.
+                let mut rng = ChaCha8Rng::seed_from_u64(seed);
+                let pixel = frame * image_width * image_height + y * image_width + x;
+                rng.set_stream(pixel as u64 + 1);
                 let sampled_pixel = (0..samples_per_pixel)
-                    .map(move |_| {
-                        let mut rng = thread_rng();
+                    .map(|_| {
                         let u = (x as f64 + rng.gen_range(0.0..1.0)) / (image_width - 1) as f64;
                         let v = (y as f64 + rng.gen_range(0.0..1.0)) / (image_height - 1) as f64;
-                        let ray = camera_ref.get_ray(u, v, &mut rng);
-                        ray.color(&background, world_ref, MAX_DEPTH, &mut rng)
+                        let ray = camera.get_ray(u, v, &mut rng);
+                        integrator.color(ray, &mut rng)
                     })
                     .sum();
                 bar.inc(1);
@@ -345,14 +477,114 @@ fn main() {
 
     bar.finish();
 
-    // Serialize to PNM
-    let mut pnm_data = format!("P3\n{} {}\n255\n\n", image_width, image_height);
-    for pixel in image {
-        pnm_data += &pnm_color(pixel, samples_per_pixel);
-    }
+    let scale = 1.0 / samples_per_pixel as f64;
+    image.into_iter().map(|pixel| pixel * scale).collect()
+}
+
+fn builtin_scene(world: usize, rng: &mut dyn RngCore) -> Scene {
+    let sky = Color::new(0.70, 0.80, 1.00);
+    let (world, camera, background) = match world {
+        1 => (
+            two_spheres(rng),
+            CameraSettings {
+                lookfrom: [13.0, 2.0, 3.0],
+                lookat: [0.0, 0.0, 0.0],
+                vfov: 20.0,
+                ..Default::default()
+            },
+            sky,
+        ),
+        2 => (
+            two_perlin_spheres(rng),
+            CameraSettings {
+                lookfrom: [13.0, 2.0, 3.0],
+                lookat: [0.0, 0.0, 0.0],
+                vfov: 20.0,
+                ..Default::default()
+            },
+            sky,
+        ),
+        3 => (
+            earth(rng),
+            CameraSettings {
+                lookfrom: [13.0, 2.0, 3.0],
+                lookat: [0.0, 0.0, 0.0],
+                vfov: 20.0,
+                ..Default::default()
+            },
+            sky,
+        ),
+        // Run with -s 400
+        4 => (
+            simple_light(rng),
+            CameraSettings {
+                lookfrom: [26.0, 3.0, 6.0],
+                lookat: [0.0, 2.0, 0.0],
+                vfov: 20.0,
+                ..Default::default()
+            },
+            color::black(),
+        ),
+        // Run with -s 200 -x 600 -y 600
+        5 => (
+            cornell_box(rng),
+            CameraSettings {
+                lookfrom: [278.0, 278.0, -800.0],
+                lookat: [278.0, 278.0, 0.0],
+                vfov: 40.0,
+                ..Default::default()
+            },
+            color::black(),
+        ),
+        // Run with -s 200 -x 600 -y 600
+        6 => (
+            cornell_smoke(rng),
+            CameraSettings {
+                lookfrom: [278.0, 278.0, -800.0],
+                lookat: [278.0, 278.0, 0.0],
+                vfov: 40.0,
+                ..Default::default()
+            },
+            color::black(),
+        ),
+        // Run with -s 10000 -x 800 -y 800
+        7 => (
+            final_scene(rng),
+            CameraSettings {
+                lookfrom: [478.0, 278.0, -600.0],
+                lookat: [278.0, 278.0, 0.0],
+                vfov: 40.0,
+                ..Default::default()
+            },
+            color::black(),
+        ),
+        8 => (
+            forest(rng),
+            CameraSettings {
+                lookfrom: [0.0, 10.0, 60.0],
+                lookat: [0.0, 0.0, 0.0],
+                vfov: 40.0,
+                ..Default::default()
+            },
+            sky,
+        ),
+        _ => (
+            random_scene(rng),
+            CameraSettings {
+                lookfrom: [13.0, 2.0, 3.0],
+                lookat: [0.0, 0.0, 0.0],
+                vfov: 20.0,
+                aperture: 0.1,
+                ..Default::default()
+            },
+            sky,
+        ),
+    };
 
-    match load_from_memory_with_format(&pnm_data.into_bytes(), ImageFormat::Pnm) {
-        Ok(img) => img.save("raytracer.png").unwrap(),
-        Err(err) => println!("{}", err),
+    Scene {
+        world,
+        camera,
+        lights: vec![],
+        environment: Box::new(Uniform::new(background)),
     }
 }
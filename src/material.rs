@@ -1,12 +1,13 @@
 use crate::{
     color::{self, Color},
     hitable::HitRecord,
-    ray::Ray,
+    microfacet::{Ggx, Gtr1},
     texture::*,
 };
 use cgmath::{InnerSpace, Point3, Vector3};
 use rand::{Rng, RngCore};
 use raytracer::{random_in_unit_sphere, random_unit_vector};
+use std::{f64::consts::PI, ops::BitOr, sync::Arc};
 
 // Return true if the vector is close to zero in all dimensions.
 fn near_zero(v: Vector3<f64>) -> bool {
@@ -26,48 +27,152 @@ fn refract(uv: Vector3<f64>, n: Vector3<f64>, etai_over_etat: f64) -> Vector3<f6
     r_out_perp + r_out_parallel
 }
 
+/// Kind of scattering a sampled direction results from, combined with `|`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BsdfFlags(u8);
+
+impl BsdfFlags {
+    pub const REFLECTION: Self = Self(1);
+    pub const TRANSMISSION: Self = Self(1 << 1);
+    pub const DIFFUSE: Self = Self(1 << 2);
+    pub const GLOSSY: Self = Self(1 << 3);
+    /// Drawn from a delta distribution (mirror, glass), which `eval` and `pdf` do not cover
+    /// and light sampling cannot reproduce.
+    pub const SPECULAR: Self = Self(1 << 4);
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_specular(self) -> bool {
+        self.contains(Self::SPECULAR)
+    }
+
+    /// Reflection or transmission, depending on the side of the surface `wi` lies on
+    fn side(wi: Vector3<f64>) -> Self {
+        if wi.z > 0.0 {
+            Self::REFLECTION
+        } else {
+            Self::TRANSMISSION
+        }
+    }
+}
+
+impl BitOr for BsdfFlags {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+pub struct BsdfSample {
+    /// Incident direction in the local shading frame
+    pub wi: Vector3<f64>,
+    /// BSDF times cosine divided by `pdf`, the factor applied to the path throughput
+    pub value: Color,
+    /// Solid angle pdf of `wi`. For specular samples, the probability of choosing that lobe.
+    pub pdf: f64,
+    pub flags: BsdfFlags,
+}
+
+/// Scattering at a surface or in a medium. Directions are unit vectors in the local shading
+/// frame of the hit, whose z axis is `record.normal`, and point away from the hit: `wo`
+/// towards the viewer and `wi` towards the light.
 pub trait Material: Send + Sync {
-    fn scatter(&self, ray: &Ray, record: &HitRecord, rng: &mut dyn RngCore)
-        -> Option<(Color, Ray)>;
+    /// Sample the direction light arrives from, `None` if the path is absorbed.
+    fn sample(
+        &self,
+        record: &HitRecord,
+        wo: Vector3<f64>,
+        rng: &mut dyn RngCore,
+    ) -> Option<BsdfSample>;
+
+    /// BSDF times cosine for light arriving from `wi`. Specular lobes do not contribute.
+    fn eval(&self, _record: &HitRecord, _wo: Vector3<f64>, _wi: Vector3<f64>) -> Color {
+        color::black()
+    }
+
+    /// Solid angle pdf of `sample` choosing `wi`, excluding specular lobes.
+    fn pdf(&self, _record: &HitRecord, _wo: Vector3<f64>, _wi: Vector3<f64>) -> f64 {
+        0.0
+    }
 
     fn emitted(&self, _u: f64, _v: f64, _p: &Point3<f64>) -> Color {
         color::black()
     }
+
+    fn is_emissive(&self) -> bool {
+        false
+    }
+}
+
+impl<T: Material + ?Sized> Material for Arc<T> {
+    fn sample(
+        &self,
+        record: &HitRecord,
+        wo: Vector3<f64>,
+        rng: &mut dyn RngCore,
+    ) -> Option<BsdfSample> {
+        self.as_ref().sample(record, wo, rng)
+    }
+
+    fn eval(&self, record: &HitRecord, wo: Vector3<f64>, wi: Vector3<f64>) -> Color {
+        self.as_ref().eval(record, wo, wi)
+    }
+
+    fn pdf(&self, record: &HitRecord, wo: Vector3<f64>, wi: Vector3<f64>) -> f64 {
+        self.as_ref().pdf(record, wo, wi)
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: &Point3<f64>) -> Color {
+        self.as_ref().emitted(u, v, p)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.as_ref().is_emissive()
+    }
 }
 
-#[derive(Clone)]
 pub struct Lambertian {
-    albedo: Box<dyn Texture>,
+    albedo: Arc<dyn Texture>,
 }
 
 impl Lambertian {
-    pub fn new(albedo: Box<dyn Texture>) -> Self {
+    pub fn new(albedo: Arc<dyn Texture>) -> Self {
         Self { albedo }
     }
 }
 
 impl Material for Lambertian {
-    fn scatter(
+    fn sample(
         &self,
-        ray: &Ray,
         record: &HitRecord,
+        _wo: Vector3<f64>,
         rng: &mut dyn RngCore,
-    ) -> Option<(Color, Ray)> {
-        let scatter_direction = record.normal + random_unit_vector(rng);
+    ) -> Option<BsdfSample> {
+        let wi = sample_cosine(rng);
+        Some(BsdfSample {
+            wi,
+            value: self.albedo.value(record.u, record.v, &record.p),
+            pdf: wi.z / PI,
+            flags: BsdfFlags::DIFFUSE | BsdfFlags::REFLECTION,
+        })
+    }
 
-        // Catch degenerate scatter direction
-        let scatter_direction = if near_zero(scatter_direction) {
-            record.normal
-        } else {
-            scatter_direction
-        };
+    fn eval(&self, record: &HitRecord, wo: Vector3<f64>, wi: Vector3<f64>) -> Color {
+        self.albedo.value(record.u, record.v, &record.p) * self.pdf(record, wo, wi)
+    }
 
-        let scattered = Ray::new(record.p, scatter_direction, ray.time());
-        Some((self.albedo.value(record.u, record.v, &record.p), scattered))
+    fn pdf(&self, _record: &HitRecord, _wo: Vector3<f64>, wi: Vector3<f64>) -> f64 {
+        if wi.z > 0.0 {
+            wi.z / PI
+        } else {
+            0.0
+        }
     }
 }
 
-#[derive(Clone)]
 pub struct Metal {
     albedo: Color,
     fuzz: f64,
@@ -84,27 +189,167 @@ impl Metal {
 }
 
 impl Material for Metal {
-    fn scatter(
+    fn sample(
         &self,
-        ray: &Ray,
-        record: &HitRecord,
+        _record: &HitRecord,
+        wo: Vector3<f64>,
         rng: &mut dyn RngCore,
-    ) -> Option<(Color, Ray)> {
-        let reflected = reflect(ray.direction().normalize(), record.normal);
-        let scattered = Ray::new(
-            record.p,
-            reflected + (self.fuzz * random_in_unit_sphere(rng)),
-            ray.time(),
-        );
-        if scattered.direction().dot(record.normal) > 0.0 {
-            Some((self.albedo, scattered))
+    ) -> Option<BsdfSample> {
+        // The fuzzed direction has no pdf, so it is treated like a mirror
+        let reflected = reflect(-wo, Vector3::new(0.0, 0.0, 1.0));
+        let wi = reflected + self.fuzz * random_in_unit_sphere(rng);
+        if wi.z > 0.0 {
+            Some(BsdfSample {
+                wi: wi.normalize(),
+                value: self.albedo,
+                pdf: 1.0,
+                flags: BsdfFlags::SPECULAR | BsdfFlags::REFLECTION,
+            })
         } else {
             None
         }
     }
 }
 
-#[derive(Clone)]
+/// Scalar parameter given by the mean of the channels of `texture`
+fn scalar_value(texture: &dyn Texture, record: &HitRecord) -> f64 {
+    let value = texture.value(record.u, record.v, &record.p);
+    (value.x + value.y + value.z) / 3.0
+}
+
+/// GGX distribution whose roughness is the mean of the channels of `roughness`
+fn distribution(roughness: &dyn Texture, record: &HitRecord) -> Ggx {
+    Ggx::new(scalar_value(roughness, record))
+}
+
+/// Measured complex refraction indices `(eta, k)` of metals at 650, 550 and 450 nm
+#[derive(Clone, Copy)]
+pub enum ConductorPreset {
+    Gold,
+    Copper,
+    Aluminium,
+    Silver,
+}
+
+impl ConductorPreset {
+    pub fn ior(self) -> (Color, Color) {
+        match self {
+            ConductorPreset::Gold => (
+                Color::new(0.143, 0.374, 1.442),
+                Color::new(3.983, 2.385, 1.603),
+            ),
+            ConductorPreset::Copper => (
+                Color::new(0.200, 0.924, 1.102),
+                Color::new(3.912, 2.452, 2.142),
+            ),
+            ConductorPreset::Aluminium => (
+                Color::new(1.657, 0.880, 0.521),
+                Color::new(9.224, 6.270, 4.837),
+            ),
+            ConductorPreset::Silver => (
+                Color::new(0.155, 0.117, 0.138),
+                Color::new(4.828, 3.122, 2.147),
+            ),
+        }
+    }
+}
+
+/// Fresnel reflectance of a conductor with complex refraction index `eta + i k`.
+fn fresnel_conductor(cos_theta: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = cos_theta * cos_theta;
+    let sin2 = 1.0 - cos2;
+    let t0 = eta * eta - k * k - sin2;
+    let a2b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let a = (0.5 * (a2b2 + t0)).max(0.0).sqrt();
+    let t1 = a2b2 + cos2;
+    let t2 = 2.0 * a * cos_theta;
+    let rs = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    0.5 * (rs + rp)
+}
+
+/// Rough metal: GGX microfacets reflecting according to the conductor Fresnel equations.
+/// Roughness is read from the mean of the texture channels.
+pub struct Conductor {
+    eta: Color,
+    k: Color,
+    roughness: Arc<dyn Texture>,
+}
+
+impl Conductor {
+    pub fn new(eta: Color, k: Color, roughness: Arc<dyn Texture>) -> Self {
+        Self { eta, k, roughness }
+    }
+
+    pub fn preset(preset: ConductorPreset, roughness: Arc<dyn Texture>) -> Self {
+        let (eta, k) = preset.ior();
+        Self::new(eta, k, roughness)
+    }
+
+    fn fresnel(&self, cos_theta: f64) -> Color {
+        Color::new(
+            fresnel_conductor(cos_theta, self.eta.x, self.k.x),
+            fresnel_conductor(cos_theta, self.eta.y, self.k.y),
+            fresnel_conductor(cos_theta, self.eta.z, self.k.z),
+        )
+    }
+}
+
+impl Material for Conductor {
+    fn sample(
+        &self,
+        record: &HitRecord,
+        wo: Vector3<f64>,
+        rng: &mut dyn RngCore,
+    ) -> Option<BsdfSample> {
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let ggx = distribution(self.roughness.as_ref(), record);
+        if ggx.is_smooth() {
+            return Some(BsdfSample {
+                wi: Vector3::new(-wo.x, -wo.y, wo.z),
+                value: self.fresnel(wo.z),
+                pdf: 1.0,
+                flags: BsdfFlags::SPECULAR | BsdfFlags::REFLECTION,
+            });
+        }
+
+        let h = ggx.sample_visible_normal(wo, rng);
+        let wi = 2.0 * wo.dot(h) * h - wo;
+        if wi.z <= 0.0 {
+            return None;
+        }
+        // f * cos / pdf reduces to F * G / G1
+        Some(BsdfSample {
+            wi,
+            value: self.fresnel(wo.dot(h)) * (ggx.g(wo, wi) / ggx.g1(wo)),
+            pdf: ggx.visible_normal_pdf(wo, h) / (4.0 * wo.dot(h)),
+            flags: BsdfFlags::GLOSSY | BsdfFlags::REFLECTION,
+        })
+    }
+
+    fn eval(&self, record: &HitRecord, wo: Vector3<f64>, wi: Vector3<f64>) -> Color {
+        let ggx = distribution(self.roughness.as_ref(), record);
+        if wo.z <= 0.0 || wi.z <= 0.0 || ggx.is_smooth() {
+            return color::black();
+        }
+        let h = (wo + wi).normalize();
+        self.fresnel(wo.dot(h)) * (ggx.d(h) * ggx.g(wo, wi) / (4.0 * wo.z))
+    }
+
+    fn pdf(&self, record: &HitRecord, wo: Vector3<f64>, wi: Vector3<f64>) -> f64 {
+        let ggx = distribution(self.roughness.as_ref(), record);
+        if wo.z <= 0.0 || wi.z <= 0.0 || ggx.is_smooth() {
+            return 0.0;
+        }
+        let h = (wo + wi).normalize();
+        ggx.visible_normal_pdf(wo, h) / (4.0 * wo.dot(h))
+    }
+}
+
 pub struct Dielectric {
     refraction_index: f64,
 }
@@ -123,84 +368,544 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(
+    fn sample(
         &self,
-        ray: &Ray,
         record: &HitRecord,
+        wo: Vector3<f64>,
         rng: &mut dyn RngCore,
-    ) -> Option<(Color, Ray)> {
-        let attenuation = Color::new(1.0, 1.0, 1.0);
+    ) -> Option<BsdfSample> {
         let refraction_ratio = if record.front_face {
             1.0 / self.refraction_index
         } else {
             self.refraction_index
         };
 
-        let unit_direction = ray.direction().normalize();
-        let cos_theta = (-unit_direction).dot(record.normal).min(1.0);
+        let normal = Vector3::new(0.0, 0.0, 1.0);
+        let cos_theta = wo.z.min(1.0);
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
-        let cannot_refract = refraction_ratio * sin_theta > 1.0;
-        let direction = if cannot_refract
-            || Self::reflectance(cos_theta, refraction_ratio) > rng.gen_range(0.0..1.0)
-        {
-            reflect(unit_direction, record.normal)
+        let reflectance = if refraction_ratio * sin_theta > 1.0 {
+            1.0
         } else {
-            refract(unit_direction, record.normal, refraction_ratio)
+            Self::reflectance(cos_theta, refraction_ratio)
         };
-        let scattered = Ray::new(record.p, direction, ray.time());
-        Some((attenuation, scattered))
+        let sample = if reflectance > rng.gen_range(0.0..1.0) {
+            BsdfSample {
+                wi: reflect(-wo, normal),
+                value: color::white(),
+                pdf: reflectance,
+                flags: BsdfFlags::SPECULAR | BsdfFlags::REFLECTION,
+            }
+        } else {
+            BsdfSample {
+                wi: refract(-wo, normal, refraction_ratio).normalize(),
+                value: color::white(),
+                pdf: 1.0 - reflectance,
+                flags: BsdfFlags::SPECULAR | BsdfFlags::TRANSMISSION,
+            }
+        };
+        Some(sample)
+    }
+}
+
+/// Fresnel reflectance of unpolarized light at a dielectric interface, `eta` being the
+/// refraction index of the far side relative to the near one.
+fn fresnel_dielectric(cos_theta: f64, eta: f64) -> f64 {
+    let sin2_t = (1.0 - cos_theta * cos_theta) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let rs = (cos_theta - eta * cos_t) / (cos_theta + eta * cos_t);
+    let rp = (eta * cos_theta - cos_t) / (eta * cos_theta + cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+/// Half vector of a transmission from `wo` to `wi`, oriented along the normal, if the
+/// configuration is physically possible.
+fn transmission_half_vector(wo: Vector3<f64>, wi: Vector3<f64>, eta: f64) -> Option<Vector3<f64>> {
+    let h = (wo + eta * wi).normalize();
+    let h = if h.z < 0.0 { -h } else { h };
+    if wo.dot(h) > 0.0 && wi.dot(h) < 0.0 {
+        Some(h)
+    } else {
+        None
+    }
+}
+
+/// Reflect or refract `wo` at a microfacet sampled from `ggx`, choosing by its Fresnel
+/// reflectance. `eta` is the refraction index below the surface relative to above.
+fn sample_dielectric(
+    ggx: &Ggx,
+    wo: Vector3<f64>,
+    eta: f64,
+    rng: &mut dyn RngCore,
+) -> Option<Vector3<f64>> {
+    let h = if ggx.is_smooth() {
+        Vector3::new(0.0, 0.0, 1.0)
+    } else {
+        ggx.sample_visible_normal(wo, rng)
+    };
+    let cos_o = wo.dot(h);
+    if rng.gen_range(0.0..1.0) < fresnel_dielectric(cos_o, eta) {
+        let wi = 2.0 * cos_o * h - wo;
+        (wi.z > 0.0).then_some(wi)
+    } else {
+        let cos_t = (1.0 - (1.0 - cos_o * cos_o) / (eta * eta)).sqrt();
+        let wi = (cos_o / eta - cos_t) * h - wo / eta;
+        (wi.z < 0.0).then_some(wi)
+    }
+}
+
+/// BSDF times cosine of a rough dielectric interface
+fn dielectric_bsdf(ggx: &Ggx, wo: Vector3<f64>, wi: Vector3<f64>, eta: f64) -> f64 {
+    if wo.z <= 0.0 || wi.z == 0.0 || ggx.is_smooth() {
+        return 0.0;
+    }
+    if wi.z > 0.0 {
+        let h = (wo + wi).normalize();
+        fresnel_dielectric(wo.dot(h), eta) * ggx.d(h) * ggx.g(wo, wi) / (4.0 * wo.z)
+    } else {
+        match transmission_half_vector(wo, wi, eta) {
+            // Radiance is compressed by 1 / eta^2 when entering the denser medium
+            Some(h) => {
+                let denominator = wo.dot(h) + eta * wi.dot(h);
+                (1.0 - fresnel_dielectric(wo.dot(h), eta))
+                    * ggx.d(h)
+                    * ggx.g(wo, wi)
+                    * (wi.dot(h) * wo.dot(h)).abs()
+                    / (wo.z * denominator * denominator)
+            }
+            None => 0.0,
+        }
+    }
+}
+
+/// Solid angle pdf of `sample_dielectric` choosing `wi`
+fn dielectric_pdf(ggx: &Ggx, wo: Vector3<f64>, wi: Vector3<f64>, eta: f64) -> f64 {
+    if wo.z <= 0.0 || wi.z == 0.0 || ggx.is_smooth() {
+        return 0.0;
+    }
+    if wi.z > 0.0 {
+        let h = (wo + wi).normalize();
+        fresnel_dielectric(wo.dot(h), eta) * ggx.visible_normal_pdf(wo, h) / (4.0 * wo.dot(h))
+    } else {
+        match transmission_half_vector(wo, wi, eta) {
+            Some(h) => {
+                let denominator = wo.dot(h) + eta * wi.dot(h);
+                (1.0 - fresnel_dielectric(wo.dot(h), eta))
+                    * ggx.visible_normal_pdf(wo, h)
+                    * eta
+                    * eta
+                    * wi.dot(h).abs()
+                    / (denominator * denominator)
+            }
+            None => 0.0,
+        }
+    }
+}
+
+/// Frosted and tinted glass: GGX microfacets reflecting and refracting light (Walter et al.
+/// 2007, "Microfacet Models for Refraction through Rough Surfaces"), and Beer-Lambert
+/// absorption inside. `absorption` is the absorption coefficient per unit length for each
+/// color channel, so light crossing a distance `d` is scaled by `exp(-absorption * d)`.
+/// Absorption is applied when leaving through a back face, which assumes closed surfaces.
+pub struct RoughDielectric {
+    refraction_index: f64,
+    roughness: Arc<dyn Texture>,
+    absorption: Color,
+}
+
+impl RoughDielectric {
+    pub fn new(refraction_index: f64, roughness: Arc<dyn Texture>, absorption: Color) -> Self {
+        Self {
+            refraction_index,
+            roughness,
+            absorption,
+        }
+    }
+
+    /// Refraction index of the far side relative to the side the ray comes from
+    fn eta(&self, record: &HitRecord) -> f64 {
+        if record.front_face {
+            self.refraction_index
+        } else {
+            1.0 / self.refraction_index
+        }
+    }
+
+    /// Transmittance of the path inside the medium which ends at `record`. Paths are traced
+    /// with rays of unit length, so `t` is the distance travelled.
+    fn transmittance(&self, record: &HitRecord) -> Color {
+        if record.front_face {
+            return color::white();
+        }
+        self.absorption.map(|a| (-a * record.t).exp())
+    }
+}
+
+impl Material for RoughDielectric {
+    fn sample(
+        &self,
+        record: &HitRecord,
+        wo: Vector3<f64>,
+        rng: &mut dyn RngCore,
+    ) -> Option<BsdfSample> {
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let eta = self.eta(record);
+        let ggx = distribution(self.roughness.as_ref(), record);
+        let wi = sample_dielectric(&ggx, wo, eta, rng)?;
+        let (value, pdf, flags) = if ggx.is_smooth() {
+            let fresnel = fresnel_dielectric(wo.z, eta);
+            if wi.z > 0.0 {
+                (1.0, fresnel, BsdfFlags::SPECULAR)
+            } else {
+                (1.0 / (eta * eta), 1.0 - fresnel, BsdfFlags::SPECULAR)
+            }
+        } else {
+            let pdf = dielectric_pdf(&ggx, wo, wi, eta);
+            if pdf <= 0.0 {
+                return None;
+            }
+            (
+                dielectric_bsdf(&ggx, wo, wi, eta) / pdf,
+                pdf,
+                BsdfFlags::GLOSSY,
+            )
+        };
+
+        Some(BsdfSample {
+            wi,
+            value: self.transmittance(record) * value,
+            pdf,
+            flags: flags | BsdfFlags::side(wi),
+        })
+    }
+
+    fn eval(&self, record: &HitRecord, wo: Vector3<f64>, wi: Vector3<f64>) -> Color {
+        let ggx = distribution(self.roughness.as_ref(), record);
+        self.transmittance(record) * dielectric_bsdf(&ggx, wo, wi, self.eta(record))
+    }
+
+    fn pdf(&self, record: &HitRecord, wo: Vector3<f64>, wi: Vector3<f64>) -> f64 {
+        let ggx = distribution(self.roughness.as_ref(), record);
+        dielectric_pdf(&ggx, wo, wi, self.eta(record))
+    }
+}
+
+/// Uniform gray texture
+fn constant(value: f64) -> Arc<dyn Texture> {
+    Arc::new(SolidColor::new(&Color::new(value, value, value)))
+}
+
+/// Schlick's approximation of the Fresnel reflectance, `f0` being the reflectance at normal
+/// incidence.
+fn schlick(f0: Color, cos_theta: f64) -> Color {
+    let weight = (1.0 - cos_theta).clamp(0.0, 1.0).powi(5);
+    f0 + (color::white() - f0) * weight
+}
+
+/// Cosine weighted direction in the upper hemisphere of the local frame
+fn sample_cosine(rng: &mut dyn RngCore) -> Vector3<f64> {
+    let direction = Vector3::new(0.0, 0.0, 1.0) + random_unit_vector(rng);
+    if near_zero(direction) {
+        Vector3::new(0.0, 0.0, 1.0)
+    } else {
+        direction.normalize()
+    }
+}
+
+/// Disney's principled BSDF (Burley 2012 and 2015), layering a diffuse base with retro-reflection
+/// and sheen, a GGX specular, a rough glass transmission and a clear coat. All parameters except
+/// `refraction_index` are textures; scalar ones read the mean of the channels. `new` sets the
+/// defaults, which give a rough white plastic.
+pub struct Principled {
+    pub base_color: Arc<dyn Texture>,
+    /// Blend from dielectric (0) to metal (1), whose specular is tinted by the base color
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+    /// Dielectric specular intensity, 0.5 being a reflectance of 4% at normal incidence
+    pub specular: Arc<dyn Texture>,
+    /// Tints the dielectric specular and the sheen towards the hue of the base color
+    pub specular_tint: Arc<dyn Texture>,
+    /// Additional grazing reflection, for cloth
+    pub sheen: Arc<dyn Texture>,
+    pub clearcoat: Arc<dyn Texture>,
+    pub clearcoat_gloss: Arc<dyn Texture>,
+    /// Blend from opaque (0) to glass (1) tinted by the base color
+    pub transmission: Arc<dyn Texture>,
+    pub refraction_index: f64,
+}
+
+impl Principled {
+    pub fn new(base_color: Arc<dyn Texture>) -> Self {
+        Self {
+            base_color,
+            metallic: constant(0.0),
+            roughness: constant(0.5),
+            specular: constant(0.5),
+            specular_tint: constant(0.0),
+            sheen: constant(0.0),
+            clearcoat: constant(0.0),
+            clearcoat_gloss: constant(1.0),
+            transmission: constant(0.0),
+            refraction_index: 1.5,
+        }
+    }
+
+    fn lobes(&self, record: &HitRecord) -> PrincipledLobes {
+        let scalar =
+            |texture: &Arc<dyn Texture>| scalar_value(texture.as_ref(), record).clamp(0.0, 1.0);
+        let base_color = self.base_color.value(record.u, record.v, &record.p);
+        let metallic = scalar(&self.metallic);
+        let transmission = scalar(&self.transmission) * (1.0 - metallic);
+        let luminance = color::luminance(base_color);
+        let tint = if luminance > 0.0 {
+            base_color / luminance
+        } else {
+            color::white()
+        };
+        let specular_tint = scalar(&self.specular_tint);
+        let dielectric_tint = color::white() * (1.0 - specular_tint) + tint * specular_tint;
+        let dielectric_f0 = 0.08 * scalar(&self.specular) * dielectric_tint;
+        let clearcoat_gloss = scalar(&self.clearcoat_gloss);
+        // The roughness is bounded so every lobe can be evaluated, keeping them non-specular
+        let roughness = scalar(&self.roughness).max(0.05);
+        PrincipledLobes {
+            base_color,
+            roughness,
+            ggx: Ggx::new(roughness),
+            specular_f0: dielectric_f0 * (1.0 - metallic) + base_color * metallic,
+            sheen: scalar(&self.sheen) * dielectric_tint,
+            clearcoat: scalar(&self.clearcoat),
+            clearcoat_distribution: Gtr1::new(
+                0.1 * (1.0 - clearcoat_gloss) + 0.001 * clearcoat_gloss,
+            ),
+            diffuse_weight: (1.0 - metallic) * (1.0 - transmission),
+            specular_weight: 1.0 - transmission,
+            transmission,
+            eta: if record.front_face {
+                self.refraction_index
+            } else {
+                1.0 / self.refraction_index
+            },
+        }
+    }
+}
+
+/// Parameters of `Principled` evaluated at a hit point, in the local frame of the surface
+struct PrincipledLobes {
+    base_color: Color,
+    roughness: f64,
+    ggx: Ggx,
+    specular_f0: Color,
+    sheen: Color,
+    clearcoat: f64,
+    clearcoat_distribution: Gtr1,
+    diffuse_weight: f64,
+    specular_weight: f64,
+    transmission: f64,
+    eta: f64,
+}
+
+impl PrincipledLobes {
+    const CLEARCOAT_F0: f64 = 0.04;
+
+    /// Probabilities of sampling the diffuse, specular, transmission and clear coat lobes,
+    /// roughly following their contributions
+    fn probabilities(&self, wo: Vector3<f64>) -> [f64; 4] {
+        let clearcoat_f0 = color::white() * Self::CLEARCOAT_F0;
+        let weights = [
+            self.diffuse_weight
+                * (color::luminance(self.base_color) + color::luminance(self.sheen)),
+            self.specular_weight * color::luminance(schlick(self.specular_f0, wo.z)),
+            self.transmission,
+            0.25 * self.clearcoat * color::luminance(schlick(clearcoat_f0, wo.z)),
+        ];
+        let total: f64 = weights.iter().sum();
+        if total > 0.0 {
+            weights.map(|weight| weight / total)
+        } else {
+            [0.0; 4]
+        }
+    }
+
+    /// Direction sampled from one of the lobes, with the kind of that lobe
+    fn sample(&self, wo: Vector3<f64>, rng: &mut dyn RngCore) -> Option<(Vector3<f64>, BsdfFlags)> {
+        let [diffuse, specular, transmission, _] = self.probabilities(wo);
+        let u = rng.gen_range(0.0..1.0);
+        let (wi, flags) = if u < diffuse {
+            (sample_cosine(rng), BsdfFlags::DIFFUSE)
+        } else if u < diffuse + specular {
+            let h = self.ggx.sample_visible_normal(wo, rng);
+            (2.0 * wo.dot(h) * h - wo, BsdfFlags::GLOSSY)
+        } else if u < diffuse + specular + transmission {
+            (
+                sample_dielectric(&self.ggx, wo, self.eta, rng)?,
+                BsdfFlags::GLOSSY,
+            )
+        } else {
+            let h = self.clearcoat_distribution.sample_normal(rng);
+            (2.0 * wo.dot(h) * h - wo, BsdfFlags::GLOSSY)
+        };
+        Some((wi, flags | BsdfFlags::side(wi)))
+    }
+
+    /// BSDF times cosine
+    fn eval(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> Color {
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return color::black();
+        }
+        let glass = self.transmission * dielectric_bsdf(&self.ggx, wo, wi, self.eta);
+        if wi.z < 0.0 {
+            return self.base_color * glass;
+        }
+
+        let h = (wo + wi).normalize();
+        let cos_d = wi.dot(h);
+        let fresnel_weight = |cos: f64| (1.0 - cos).clamp(0.0, 1.0).powi(5);
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let retro_reflection = (1.0 + (fd90 - 1.0) * fresnel_weight(wi.z))
+            * (1.0 + (fd90 - 1.0) * fresnel_weight(wo.z));
+        let diffuse =
+            self.base_color * (retro_reflection / PI) + self.sheen * fresnel_weight(cos_d);
+
+        let specular = schlick(self.specular_f0, wo.dot(h))
+            * (self.ggx.d(h) * self.ggx.g(wo, wi) / (4.0 * wo.z));
+
+        let clearcoat_masking = Ggx::new(0.5).g(wo, wi);
+        let clearcoat = 0.25
+            * self.clearcoat
+            * schlick(color::white() * Self::CLEARCOAT_F0, wo.dot(h)).x
+            * self.clearcoat_distribution.d(h)
+            * clearcoat_masking
+            / (4.0 * wo.z);
+
+        diffuse * (self.diffuse_weight * wi.z)
+            + specular * self.specular_weight
+            + color::white() * (glass + clearcoat)
+    }
+
+    /// Solid angle pdf of `sample` choosing `wi`
+    fn pdf(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> f64 {
+        if wo.z <= 0.0 || wi.z == 0.0 {
+            return 0.0;
+        }
+        let [diffuse, specular, transmission, clearcoat] = self.probabilities(wo);
+        let glass = transmission * dielectric_pdf(&self.ggx, wo, wi, self.eta);
+        if wi.z < 0.0 {
+            return glass;
+        }
+        let h = (wo + wi).normalize();
+        diffuse * wi.z / PI
+            + specular * self.ggx.visible_normal_pdf(wo, h) / (4.0 * wo.dot(h))
+            + glass
+            + clearcoat * self.clearcoat_distribution.normal_pdf(h) / (4.0 * wo.dot(h))
+    }
+}
+
+impl Material for Principled {
+    fn sample(
+        &self,
+        record: &HitRecord,
+        wo: Vector3<f64>,
+        rng: &mut dyn RngCore,
+    ) -> Option<BsdfSample> {
+        if wo.z <= 0.0 {
+            return None;
+        }
+        let lobes = self.lobes(record);
+        let (wi, flags) = lobes.sample(wo, rng)?;
+        // All lobes may produce the direction, so weight by their combined pdf
+        let pdf = lobes.pdf(wo, wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            value: lobes.eval(wo, wi) / pdf,
+            pdf,
+            flags,
+        })
+    }
+
+    fn eval(&self, record: &HitRecord, wo: Vector3<f64>, wi: Vector3<f64>) -> Color {
+        self.lobes(record).eval(wo, wi)
+    }
+
+    fn pdf(&self, record: &HitRecord, wo: Vector3<f64>, wi: Vector3<f64>) -> f64 {
+        self.lobes(record).pdf(wo, wi)
     }
 }
 
-#[derive(Clone)]
 pub struct DiffuseLight {
-    emit: Box<dyn Texture>,
+    emit: Arc<dyn Texture>,
 }
 
 impl DiffuseLight {
+    pub fn new(emit: Arc<dyn Texture>) -> Self {
+        Self { emit }
+    }
+
     pub fn with_color(color: &Color) -> Self {
         Self {
-            emit: Box::new(SolidColor::new(color)),
+            emit: Arc::new(SolidColor::new(color)),
         }
     }
 }
 
 impl Material for DiffuseLight {
-    fn scatter(
+    fn sample(
         &self,
-        _ray: &Ray,
         _record: &HitRecord,
+        _wo: Vector3<f64>,
         _rng: &mut dyn RngCore,
-    ) -> Option<(Color, Ray)> {
+    ) -> Option<BsdfSample> {
         None
     }
 
     fn emitted(&self, u: f64, v: f64, p: &Point3<f64>) -> Color {
         self.emit.value(u, v, p)
     }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
 }
 
-#[derive(Clone)]
 pub struct Isotropic {
-    albedo: Box<dyn Texture>,
+    albedo: Arc<dyn Texture>,
 }
 
 impl Isotropic {
-    pub fn new(albedo: Box<dyn Texture>) -> Self {
+    pub fn new(albedo: Arc<dyn Texture>) -> Self {
         Self { albedo }
     }
 }
 
 impl Material for Isotropic {
-    fn scatter(
+    fn sample(
         &self,
-        ray: &Ray,
         record: &HitRecord,
+        _wo: Vector3<f64>,
         rng: &mut dyn RngCore,
-    ) -> Option<(Color, Ray)> {
-        let scattered = Ray::new(record.p, random_in_unit_sphere(rng), ray.time());
-        let attenuation = self.albedo.value(record.u, record.v, &record.p);
-        Some((attenuation, scattered))
+    ) -> Option<BsdfSample> {
+        let wi = random_unit_vector(rng);
+        Some(BsdfSample {
+            wi,
+            value: self.albedo.value(record.u, record.v, &record.p),
+            pdf: 1.0 / (4.0 * PI),
+            flags: BsdfFlags::DIFFUSE | BsdfFlags::side(wi),
+        })
+    }
+
+    fn eval(&self, record: &HitRecord, _wo: Vector3<f64>, _wi: Vector3<f64>) -> Color {
+        self.albedo.value(record.u, record.v, &record.p) / (4.0 * PI)
+    }
+
+    fn pdf(&self, _record: &HitRecord, _wo: Vector3<f64>, _wi: Vector3<f64>) -> f64 {
+        1.0 / (4.0 * PI)
     }
 }
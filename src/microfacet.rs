@@ -0,0 +1,121 @@
+use cgmath::{InnerSpace, Vector3};
+use rand::{Rng, RngCore};
+use std::f64::consts::PI;
+
+/// GGX (Trowbridge-Reitz) distribution of microfacet normals with Smith height-correlated
+/// masking-shadowing. All directions are given in the local frame of the surface, with the
+/// macro normal along z.
+pub struct Ggx {
+    alpha: f64,
+}
+
+impl Ggx {
+    /// The distribution width is the square of the perceptual `roughness` in [0, 1].
+    pub fn new(roughness: f64) -> Self {
+        let roughness = roughness.clamp(0.0, 1.0);
+        Self {
+            alpha: roughness * roughness,
+        }
+    }
+
+    /// Surfaces this smooth are treated as perfect specular, as the distribution becomes
+    /// too peaked to be evaluated reliably.
+    pub fn is_smooth(&self) -> bool {
+        self.alpha < 1e-3
+    }
+
+    /// Density of microfacets with normal `h`.
+    pub fn d(&self, h: Vector3<f64>) -> f64 {
+        if h.z <= 0.0 {
+            return 0.0;
+        }
+        let alpha2 = self.alpha * self.alpha;
+        let cos2 = h.z * h.z;
+        let denominator = cos2 * (alpha2 - 1.0) + 1.0;
+        alpha2 / (PI * denominator * denominator)
+    }
+
+    fn lambda(&self, v: Vector3<f64>) -> f64 {
+        let cos2 = v.z * v.z;
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        0.5 * (-1.0 + (1.0 + self.alpha * self.alpha * tan2).sqrt())
+    }
+
+    /// Fraction of microfacets visible from `v`.
+    pub fn g1(&self, v: Vector3<f64>) -> f64 {
+        1.0 / (1.0 + self.lambda(v))
+    }
+
+    /// Fraction of microfacets visible from both `wo` and `wi`.
+    pub fn g(&self, wo: Vector3<f64>, wi: Vector3<f64>) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Sample a microfacet normal visible from `wo`, which must lie in the upper hemisphere
+    /// (Heitz 2018, "Sampling the GGX Distribution of Visible Normals").
+    pub fn sample_visible_normal(&self, wo: Vector3<f64>, rng: &mut dyn RngCore) -> Vector3<f64> {
+        // Stretch the view direction to the hemisphere configuration
+        let vh = Vector3::new(self.alpha * wo.x, self.alpha * wo.y, wo.z).normalize();
+        let length2 = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if length2 > 0.0 {
+            Vector3::new(-vh.y, vh.x, 0.0) / length2.sqrt()
+        } else {
+            Vector3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = vh.cross(t1);
+
+        // Uniform point on the projected half disk
+        let r = rng.gen_range(0.0..1.0f64).sqrt();
+        let phi = 2.0 * PI * rng.gen_range(0.0..1.0);
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+
+        // Project onto the hemisphere and unstretch
+        let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+        Vector3::new(self.alpha * nh.x, self.alpha * nh.y, nh.z.max(0.0)).normalize()
+    }
+
+    /// Density of `sample_visible_normal` choosing `h`.
+    pub fn visible_normal_pdf(&self, wo: Vector3<f64>, h: Vector3<f64>) -> f64 {
+        self.g1(wo) * wo.dot(h).max(0.0) * self.d(h) / wo.z
+    }
+}
+
+/// Generalized Trowbridge-Reitz distribution with exponent 1, whose long tail gives the
+/// haze of clear coats (Burley 2012, "Physically Based Shading at Disney").
+pub struct Gtr1 {
+    alpha: f64,
+}
+
+impl Gtr1 {
+    pub fn new(alpha: f64) -> Self {
+        Self { alpha }
+    }
+
+    /// Density of microfacets with normal `h`.
+    pub fn d(&self, h: Vector3<f64>) -> f64 {
+        if h.z <= 0.0 {
+            return 0.0;
+        }
+        let alpha2 = self.alpha * self.alpha;
+        let t = 1.0 + (alpha2 - 1.0) * h.z * h.z;
+        (alpha2 - 1.0) / (PI * alpha2.ln() * t)
+    }
+
+    /// Sample a microfacet normal proportionally to `d(h) * h.z`.
+    pub fn sample_normal(&self, rng: &mut dyn RngCore) -> Vector3<f64> {
+        let alpha2 = self.alpha * self.alpha;
+        let u: f64 = rng.gen_range(0.0..1.0);
+        let cos2 = (1.0 - alpha2.powf(1.0 - u)) / (1.0 - alpha2);
+        let cos_theta = cos2.clamp(0.0, 1.0).sqrt();
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let phi = 2.0 * PI * rng.gen_range(0.0..1.0);
+        Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+    }
+
+    /// Density of `sample_normal` choosing `h`.
+    pub fn normal_pdf(&self, h: Vector3<f64>) -> f64 {
+        self.d(h) * h.z
+    }
+}
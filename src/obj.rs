@@ -0,0 +1,174 @@
+use crate::{
+    bvh::{Bvh, BvhSplit},
+    color::Color,
+    hitable::Hittable,
+    material::*,
+    texture::*,
+    triangle::{Mesh, Triangle},
+};
+use cgmath::{Point3, Vector3};
+use std::{
+    error::Error,
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+#[derive(Debug)]
+pub enum ObjError {
+    Load(tobj::LoadError),
+    Texture(PathBuf, image::ImageError),
+    Empty,
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjError::Load(err) => write!(f, "{}", err),
+            ObjError::Texture(path, err) => write!(f, "{}: {}", path.display(), err),
+            ObjError::Empty => write!(f, "no triangles found"),
+        }
+    }
+}
+
+impl Error for ObjError {}
+
+fn is_black(color: &[f64; 3]) -> bool {
+    color.iter().all(|c| *c <= 0.0)
+}
+
+/// Map an MTL material onto the closest of our materials:
+/// * an emission color (`Ke`) yields a `DiffuseLight`
+/// * a dissolve (`d`) below 1 or a glass illumination model yields a `Dielectric` using `Ni`
+/// * a reflecting illumination model, or a specular but no diffuse color, yields a `Metal`
+///   whose fuzz is derived from the Phong exponent `Ns`
+/// * anything else is `Lambertian`, textured by `map_Kd` if present
+fn material(mtl: &tobj::Material, dir: &Path) -> Result<Arc<dyn Material>, ObjError> {
+    let emission = mtl.unknown_param.get("Ke").and_then(|ke| {
+        let values = ke
+            .split_whitespace()
+            .map(|v| v.parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .ok()?;
+        match values[..] {
+            [r, g, b] => Some([r, g, b]),
+            _ => None,
+        }
+    });
+    let illum = mtl.illumination_model.unwrap_or(2);
+
+    if let Some(emission) = emission.filter(|e| !is_black(e)) {
+        return Ok(Arc::new(DiffuseLight::with_color(&Color::from(emission))));
+    }
+
+    if mtl.dissolve < 1.0 || matches!(illum, 4 | 6 | 7 | 9) {
+        let refraction_index = if mtl.optical_density > 0.0 {
+            mtl.optical_density
+        } else {
+            1.5
+        };
+        return Ok(Arc::new(Dielectric::new(refraction_index)));
+    }
+
+    if !is_black(&mtl.specular)
+        && (matches!(illum, 3 | 5 | 8)
+            || (is_black(&mtl.diffuse) && mtl.diffuse_texture.is_empty()))
+    {
+        let fuzz = (2.0 / (mtl.shininess.max(0.0) + 2.0)).sqrt();
+        return Ok(Arc::new(Metal::new(&Color::from(mtl.specular), fuzz)));
+    }
+
+    let albedo: Arc<dyn Texture> = if mtl.diffuse_texture.is_empty() {
+        Arc::new(SolidColor::new(&Color::from(mtl.diffuse)))
+    } else {
+        let path = dir.join(&mtl.diffuse_texture);
+        Arc::new(ImageTexture::open(&path).map_err(|err| ObjError::Texture(path, err))?)
+    };
+    Ok(Arc::new(Lambertian::new(albedo)))
+}
+
+/// Load a Wavefront OBJ file along with its MTL materials into a `Bvh` over all triangles.
+/// If `material` is given, it replaces the materials from the MTL file.
+pub fn load<P: AsRef<Path>>(
+    path: P,
+    material: Option<Arc<dyn Material>>,
+    time0: f64,
+    time1: f64,
+) -> Result<Bvh, ObjError> {
+    let path = path.as_ref();
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let options = tobj::LoadOptions {
+        single_index: true,
+        triangulate: true,
+        ..Default::default()
+    };
+    let (models, mtls) = tobj::load_obj(path, &options).map_err(ObjError::Load)?;
+    let mtls = match (&material, mtls) {
+        (Some(_), _) => vec![],
+        (None, Ok(mtls)) => mtls,
+        (None, Err(err)) => {
+            eprintln!("{}: materials not loaded: {}", path.display(), err);
+            vec![]
+        }
+    };
+
+    // Built on first use and shared by all models using the same MTL material
+    let mut materials: Vec<Option<Arc<dyn Material>>> = vec![None; mtls.len()];
+    let default: Arc<dyn Material> = Arc::new(Lambertian::new(Arc::new(SolidColor::new(
+        &Color::new(0.73, 0.73, 0.73),
+    ))));
+
+    let mut triangles: Vec<Box<dyn Hittable>> = vec![];
+    for model in models {
+        let mesh = model.mesh;
+        let material = match (&material, mesh.material_id.filter(|&id| id < mtls.len())) {
+            (Some(material), _) => material.clone(),
+            (None, Some(id)) => match &materials[id] {
+                Some(material) => material.clone(),
+                None => {
+                    let material = self::material(&mtls[id], dir)?;
+                    materials[id] = Some(material.clone());
+                    material
+                }
+            },
+            (None, None) => default.clone(),
+        };
+        let mesh = Arc::new(Mesh {
+            positions: mesh
+                .positions
+                .chunks_exact(3)
+                .map(|p| Point3::new(p[0], p[1], p[2]))
+                .collect(),
+            normals: mesh
+                .normals
+                .chunks_exact(3)
+                .map(|n| Vector3::new(n[0], n[1], n[2]))
+                .collect(),
+            uvs: mesh
+                .texcoords
+                .chunks_exact(2)
+                .map(|uv| (uv[0], uv[1]))
+                .collect(),
+            indices: mesh
+                .indices
+                .chunks_exact(3)
+                .map(|i| [i[0] as usize, i[1] as usize, i[2] as usize])
+                .collect(),
+            material,
+        });
+        triangles.extend(
+            mesh.triangles()
+                .map(|triangle: Triangle| Box::new(triangle) as Box<dyn Hittable>),
+        );
+    }
+
+    if triangles.is_empty() {
+        return Err(ObjError::Empty);
+    }
+    Ok(Bvh::with_split(
+        triangles,
+        time0,
+        time1,
+        BvhSplit::default(),
+    ))
+}
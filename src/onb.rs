@@ -0,0 +1,33 @@
+use cgmath::{InnerSpace, Vector3};
+
+/// Orthonormal basis, used to express directions relative to a surface normal.
+pub struct Onb {
+    u: Vector3<f64>,
+    v: Vector3<f64>,
+    w: Vector3<f64>,
+}
+
+impl Onb {
+    /// Build a basis whose `w` axis points along `n`.
+    pub fn from_w(n: Vector3<f64>) -> Self {
+        let w = n.normalize();
+        let a = if w.x.abs() > 0.9 {
+            Vector3::new(0.0, 1.0, 0.0)
+        } else {
+            Vector3::new(1.0, 0.0, 0.0)
+        };
+        let v = w.cross(a).normalize();
+        let u = w.cross(v);
+        Self { u, v, w }
+    }
+
+    /// Transform `a` from basis coordinates to world coordinates.
+    pub fn local(&self, a: Vector3<f64>) -> Vector3<f64> {
+        a.x * self.u + a.y * self.v + a.z * self.w
+    }
+
+    /// Transform `a` from world coordinates to basis coordinates.
+    pub fn to_basis(&self, a: Vector3<f64>) -> Vector3<f64> {
+        Vector3::new(a.dot(self.u), a.dot(self.v), a.dot(self.w))
+    }
+}
@@ -0,0 +1,134 @@
+use crate::{color::Color, tonemap::ToneMapping};
+use image::{
+    codecs::{
+        gif::{GifEncoder, Repeat},
+        hdr::HdrEncoder,
+    },
+    error::{EncodingError, ImageFormatHint},
+    Delay, DynamicImage, Frame, ImageError, ImageFormat, ImageResult, Rgb, Rgb32FImage, RgbImage,
+    RgbaImage,
+};
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Write the linear radiance of `image`, given row by row from the top, to `path`. The file
+/// extension selects the encoder: `.exr` and `.hdr` keep the 32-bit float values, any other
+/// format supported by `image` receives 8-bit sRGB colors after `tone_mapping`.
+pub fn save(
+    path: &Path,
+    image: &[Color],
+    width: usize,
+    height: usize,
+    tone_mapping: &ToneMapping,
+) -> ImageResult<()> {
+    let pixels = image
+        .iter()
+        .map(|c| Rgb([c.x as f32, c.y as f32, c.z as f32]));
+    match ImageFormat::from_path(path)? {
+        ImageFormat::OpenExr => {
+            let buffer = Rgb32FImage::from_vec(
+                width as u32,
+                height as u32,
+                pixels.flat_map(|p| p.0).collect(),
+            )
+            .expect("image size does not match dimensions");
+            DynamicImage::ImageRgb32F(buffer).save(path)
+        }
+        // image cannot encode Radiance HDR through `save`
+        ImageFormat::Hdr => {
+            let file = BufWriter::new(File::create(path)?);
+            HdrEncoder::new(file).encode(&pixels.collect::<Vec<_>>(), width, height)
+        }
+        _ => {
+            let buffer = RgbImage::from_vec(
+                width as u32,
+                height as u32,
+                image.iter().flat_map(|c| tone_mapping.encode(*c)).collect(),
+            )
+            .expect("image size does not match dimensions");
+            buffer.save(path)
+        }
+    }
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case(extension))
+}
+
+/// Whether `path` names an animation (`.gif` or `.apng`) rather than a single image.
+pub fn is_animation(path: &Path) -> bool {
+    has_extension(path, "gif") || has_extension(path, "apng")
+}
+
+/// `path` numbered with `frame`, e.g. `out_0042.png` for `out.png`.
+pub fn frame_path(path: &Path, frame: usize) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(format!("_{:04}", frame));
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
+
+/// Write `frames` of 8-bit sRGB colors from `ToneMapping::encode`, each `width` by `height`
+/// and given row by row from the top, to the animated GIF or APNG at `path`. The animation
+/// loops forever.
+pub fn save_animation(
+    path: &Path,
+    frames: &[Vec<u8>],
+    width: usize,
+    height: usize,
+    fps: f64,
+) -> ImageResult<()> {
+    let file = BufWriter::new(File::create(path)?);
+    if has_extension(path, "gif") {
+        let mut encoder = GifEncoder::new(file);
+        encoder.set_repeat(Repeat::Infinite)?;
+        let delay = Delay::from_saturating_duration(Duration::from_secs_f64(1.0 / fps));
+        encoder.encode_frames(frames.iter().map(|frame| {
+            let rgba = frame
+                .chunks(3)
+                .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+                .collect();
+            let buffer = RgbaImage::from_vec(width as u32, height as u32, rgba)
+                .expect("image size does not match dimensions");
+            Frame::from_parts(buffer, 0, 0, delay)
+        }))
+    } else {
+        write_apng(file, frames, width, height, fps).map_err(|err| {
+            ImageError::Encoding(EncodingError::new(
+                ImageFormatHint::Name("APNG".into()),
+                err,
+            ))
+        })
+    }
+}
+
+fn write_apng(
+    file: BufWriter<File>,
+    frames: &[Vec<u8>],
+    width: usize,
+    height: usize,
+    fps: f64,
+) -> Result<(), png::EncodingError> {
+    let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(frames.len() as u32, 0)?;
+    // The delay is a fraction of 16-bit integers, in hundredths for fractional rates
+    encoder.set_frame_delay(
+        100,
+        (100.0 * fps).round().clamp(1.0, u16::MAX as f64) as u16,
+    )?;
+    let mut writer = encoder.write_header()?;
+    for frame in frames {
+        writer.write_image_data(frame)?;
+    }
+    writer.finish()
+}
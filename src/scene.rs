@@ -0,0 +1,1211 @@
+use crate::{
+    aarect::*,
+    animation::Keyframes,
+    bvh::{Bvh, BvhSplit},
+    camera::{Aperture, Camera, Equirectangular, Fisheye, Lens, Ods, Orthographic, Perspective},
+    color::{self, Color},
+    constant_medium::ConstantMedium,
+    cuboid::Cuboid,
+    environment::{Environment, EnvironmentMap, Uniform},
+    hitable::Hittable,
+    hitable_list::HitableList,
+    instance::Instance,
+    light::{DirectionalLight, PointLight, PunctualLight, SpotLight},
+    material::*,
+    obj::{self, ObjError},
+    ray::Ray,
+    sky::Sky,
+    sphere::*,
+    texture::*,
+    transform::{Animated, Placement, Transform},
+    triangle::{Mesh, TriangleMesh},
+};
+use cgmath::{
+    Deg, InnerSpace, Matrix, Matrix4, One, Point3, Quaternion, Rotation3, SquareMatrix, Vector3,
+};
+use rand::RngCore;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// A scene as loaded from a scene description file or assembled from a built-in world.
+pub struct Scene {
+    pub world: HitableList,
+    pub camera: CameraSettings,
+    pub lights: Vec<Box<dyn PunctualLight>>,
+    pub environment: Box<dyn Environment>,
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(PathBuf, io::Error),
+    Parse(toml::de::Error),
+    Image(PathBuf, image::ImageError),
+    UnknownTexture(String),
+    UnknownMaterial(String),
+    UnknownGeometry(String),
+    Recursive(String),
+    EmptyBvh,
+    EmptyList,
+    InvalidMesh(&'static str),
+    InvalidMaterial(&'static str),
+    InvalidCamera(&'static str),
+    InvalidAnimation(&'static str),
+    InvalidTransform(&'static str),
+    InvalidEnvironment(&'static str),
+    InvalidLight(&'static str),
+    Obj(PathBuf, ObjError),
+    SingularTransform,
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            SceneError::Parse(err) => write!(f, "invalid scene description: {}", err),
+            SceneError::Image(path, err) => write!(f, "{}: {}", path.display(), err),
+            SceneError::UnknownTexture(name) => write!(f, "unknown texture '{}'", name),
+            SceneError::UnknownMaterial(name) => write!(f, "unknown material '{}'", name),
+            SceneError::UnknownGeometry(name) => write!(f, "unknown geometry '{}'", name),
+            SceneError::Recursive(name) => write!(f, "'{}' is defined in terms of itself", name),
+            SceneError::EmptyBvh => write!(f, "bvh without objects"),
+            SceneError::EmptyList => write!(f, "list without objects"),
+            SceneError::InvalidMesh(reason) => write!(f, "invalid triangle mesh: {}", reason),
+            SceneError::InvalidMaterial(reason) => write!(f, "invalid material: {}", reason),
+            SceneError::InvalidCamera(reason) => write!(f, "invalid camera: {}", reason),
+            SceneError::InvalidAnimation(reason) => write!(f, "invalid animation: {}", reason),
+            SceneError::InvalidTransform(reason) => write!(f, "invalid transformation: {}", reason),
+            SceneError::InvalidEnvironment(reason) => write!(f, "invalid environment: {}", reason),
+            SceneError::InvalidLight(reason) => write!(f, "invalid light: {}", reason),
+            SceneError::Obj(path, err) => write!(f, "{}: {}", path.display(), err),
+            SceneError::SingularTransform => write!(f, "transformation is not invertible"),
+        }
+    }
+}
+
+impl Error for SceneError {}
+
+/// How the camera maps the image to rays
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Projection {
+    Perspective,
+    Orthographic,
+    Fisheye,
+    Equirectangular,
+    /// Omni-directional stereo, equirectangular for the left eye above the right eye
+    Ods,
+}
+
+/// Shape of the lens opening
+#[derive(Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ApertureShape {
+    Disk,
+    /// `rotation` in degrees
+    Polygon {
+        blades: usize,
+        #[serde(default)]
+        rotation: f64,
+    },
+    /// Grayscale mask, white where the lens is open
+    Image {
+        path: PathBuf,
+    },
+}
+
+/// Camera placement at `time`. Properties not given keep their value from the camera or, if
+/// other keyframes give them, are interpolated between those.
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraKeyframe {
+    pub time: f64,
+    pub lookfrom: Option<[f64; 3]>,
+    pub lookat: Option<[f64; 3]>,
+    pub vfov: Option<f64>,
+}
+
+/// Camera placement. Every property is optional; without `lookfrom` and `lookat` the camera
+/// sits at the origin and looks down the negative z axis.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraSettings {
+    pub projection: Projection,
+    pub lookfrom: [f64; 3],
+    pub lookat: [f64; 3],
+    pub vup: [f64; 3],
+    /// vertical field of view in degrees, for the perspective and fisheye projections
+    pub vfov: f64,
+    /// focal length in mm, replaces `vfov` together with `sensor_height`
+    pub focal_length: Option<f64>,
+    /// sensor height in mm, 24 for full frame
+    pub sensor_height: f64,
+    /// height of the view in scene units, for the orthographic projection
+    pub height: f64,
+    /// interpupillary distance in scene units, for the ODS projection
+    pub ipd: f64,
+    /// distance at which the eyes converge, for the ODS projection. Parallel if not given.
+    pub convergence: Option<f64>,
+    /// lens diameter in scene units
+    pub aperture: f64,
+    /// relative aperture, replaces `aperture` by the focal length divided by the f-number
+    pub f_stop: Option<f64>,
+    /// length of a scene unit in meters, to convert the focal length
+    pub meters_per_unit: f64,
+    pub aperture_shape: ApertureShape,
+    pub focus_dist: f64,
+    /// focus on whatever the center of the image shows instead of at `focus_dist`
+    pub autofocus: bool,
+    /// shutter open time
+    pub time0: f64,
+    /// shutter close time
+    pub time1: f64,
+    /// animation of the placement, see `at`
+    pub keyframes: Vec<CameraKeyframe>,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            projection: Projection::Perspective,
+            lookfrom: [0.0, 0.0, 0.0],
+            lookat: [0.0, 0.0, -1.0],
+            vup: [0.0, 1.0, 0.0],
+            vfov: 40.0,
+            focal_length: None,
+            sensor_height: 24.0,
+            height: 10.0,
+            ipd: 0.064,
+            convergence: None,
+            aperture: 0.0,
+            f_stop: None,
+            meters_per_unit: 1.0,
+            aperture_shape: ApertureShape::Disk,
+            focus_dist: 10.0,
+            autofocus: false,
+            time0: 0.0,
+            time1: 1.0,
+            keyframes: vec![],
+        }
+    }
+}
+
+impl CameraSettings {
+    /// The camera placed as its keyframes say at `time`.
+    pub fn at(&self, time: f64) -> CameraSettings {
+        let animate = |value: fn(&CameraKeyframe) -> Option<[f64; 3]>, base: [f64; 3]| {
+            let keys = self
+                .keyframes
+                .iter()
+                .filter_map(|key| value(key).map(|v| (key.time, Vector3::from(v))))
+                .collect();
+            Keyframes::new(keys).map_or(base, |keyframes| keyframes.at(time).into())
+        };
+        let vfov = self
+            .keyframes
+            .iter()
+            .filter_map(|key| key.vfov.map(|vfov| (key.time, vfov)))
+            .collect();
+        CameraSettings {
+            lookfrom: animate(|key| key.lookfrom, self.lookfrom),
+            lookat: animate(|key| key.lookat, self.lookat),
+            vfov: Keyframes::new(vfov).map_or(self.vfov, |keyframes| keyframes.at(time)),
+            ..self.clone()
+        }
+    }
+
+    /// Vertical field of view in degrees
+    fn vfov(&self) -> f64 {
+        match self.focal_length {
+            Some(focal_length) => 2.0 * (self.sensor_height / (2.0 * focal_length)).atan(),
+            None => self.vfov.to_radians(),
+        }
+        .to_degrees()
+    }
+
+    /// Lens diameter in scene units
+    fn aperture(&self) -> f64 {
+        match self.f_stop {
+            Some(f_stop) => {
+                let focal_length = self.focal_length.unwrap_or_else(|| {
+                    self.sensor_height / (2.0 * (self.vfov.to_radians() / 2.0).tan())
+                });
+                focal_length / f_stop / 1000.0 / self.meters_per_unit
+            }
+            None => self.aperture,
+        }
+    }
+
+    /// Distance to the first object along the view direction, or `focus_dist` if there is
+    /// nothing or autofocus is off.
+    fn focus_dist(&self, world: &dyn Hittable) -> f64 {
+        if !self.autofocus {
+            return self.focus_dist;
+        }
+        let lookfrom = Point3::from(self.lookfrom);
+        let direction = (Point3::from(self.lookat) - lookfrom).normalize();
+        world
+            .hit(&Ray::new(lookfrom, direction, self.time0), 0.001, f64::MAX)
+            .map_or(self.focus_dist, |record| record.t)
+    }
+
+    fn lens(&self, world: &dyn Hittable) -> Result<Lens, SceneError> {
+        let aperture = match &self.aperture_shape {
+            ApertureShape::Disk => Aperture::Disk,
+            ApertureShape::Polygon { blades, .. } if *blades < 3 => {
+                return Err(SceneError::InvalidCamera(
+                    "aperture needs at least 3 blades",
+                ))
+            }
+            ApertureShape::Polygon { blades, rotation } => Aperture::Polygon {
+                blades: *blades,
+                rotation: rotation.to_radians(),
+            },
+            ApertureShape::Image { path } => {
+                Aperture::image(path).map_err(|err| SceneError::Image(path.clone(), err))?
+            }
+        };
+        Ok(Lens {
+            aperture,
+            radius: self.aperture() / 2.0,
+            focus_dist: self.focus_dist(world),
+        })
+    }
+
+    /// The camera looking into `world`, which is only needed for autofocus.
+    pub fn camera(
+        &self,
+        aspect_ratio: f64,
+        world: &dyn Hittable,
+    ) -> Result<Box<dyn Camera>, SceneError> {
+        let lookfrom = self.lookfrom.into();
+        let lookat = self.lookat.into();
+        let vup = self.vup.into();
+        Ok(match self.projection {
+            Projection::Perspective => Box::new(Perspective::new(
+                lookfrom,
+                lookat,
+                vup,
+                self.vfov(),
+                aspect_ratio,
+                self.lens(world)?,
+                self.time0,
+                self.time1,
+            )),
+            Projection::Orthographic => Box::new(Orthographic::new(
+                lookfrom,
+                lookat,
+                vup,
+                self.height,
+                aspect_ratio,
+                self.time0,
+                self.time1,
+            )),
+            Projection::Fisheye => Box::new(Fisheye::new(
+                lookfrom,
+                lookat,
+                vup,
+                self.vfov(),
+                aspect_ratio,
+                self.time0,
+                self.time1,
+            )),
+            Projection::Equirectangular => Box::new(Equirectangular::new(
+                lookfrom, lookat, vup, self.time0, self.time1,
+            )),
+            Projection::Ods => Box::new(Ods::new(
+                lookfrom,
+                lookat,
+                vup,
+                self.ipd,
+                self.convergence,
+                self.lens(world)?,
+                self.time0,
+                self.time1,
+            )),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDesc {
+    camera: CameraSettings,
+    /// Color of rays leaving the scene, unless an `environment` is given
+    #[serde(default)]
+    background: [f64; 3],
+    environment: Option<EnvironmentDesc>,
+    #[serde(default)]
+    textures: HashMap<String, TextureDesc>,
+    #[serde(default)]
+    materials: HashMap<String, MaterialDesc>,
+    #[serde(default)]
+    geometries: HashMap<String, GeometryDesc>,
+    #[serde(default)]
+    objects: Vec<ObjectDesc>,
+    #[serde(default)]
+    lights: Vec<LightDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum EnvironmentDesc {
+    /// Equirectangular HDR image, turned by `rotation` degrees around the y axis and scaled
+    /// by `intensity` (default 1)
+    Image {
+        path: PathBuf,
+        #[serde(default)]
+        rotation: f64,
+        intensity: Option<f64>,
+    },
+    /// Daylight sky with the sun towards `sun_direction`, `turbidity` (default 3) ranging from
+    /// 2 for a clear sky to 10 for a hazy one
+    Sky {
+        sun_direction: [f64; 3],
+        turbidity: Option<f64>,
+        intensity: Option<f64>,
+    },
+}
+
+/// Point and directional lights, which are not part of the geometry
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum LightDesc {
+    Point {
+        position: [f64; 3],
+        intensity: [f64; 3],
+    },
+    /// Cone of light with the half-angle `angle` in degrees, fading out from `inner_angle`
+    /// (default 80% of `angle`)
+    Spot {
+        position: [f64; 3],
+        direction: [f64; 3],
+        intensity: [f64; 3],
+        angle: f64,
+        inner_angle: Option<f64>,
+    },
+    Directional {
+        direction: [f64; 3],
+        irradiance: [f64; 3],
+    },
+}
+
+impl LightDesc {
+    fn light(&self) -> Result<Box<dyn PunctualLight>, SceneError> {
+        let light_direction = |direction: &[f64; 3]| {
+            unit_direction(*direction)
+                .ok_or(SceneError::InvalidLight("direction is zero or not finite"))
+        };
+        Ok(match self {
+            LightDesc::Point {
+                position,
+                intensity,
+            } => Box::new(PointLight::new(
+                Point3::from(*position),
+                Color::from(*intensity),
+            )),
+            LightDesc::Spot {
+                position,
+                direction,
+                intensity,
+                angle,
+                inner_angle,
+            } => Box::new(SpotLight::new(
+                Point3::from(*position),
+                light_direction(direction)?,
+                Color::from(*intensity),
+                *angle,
+                inner_angle.unwrap_or(0.8 * angle),
+            )),
+            LightDesc::Directional {
+                direction,
+                irradiance,
+            } => Box::new(DirectionalLight::new(
+                light_direction(direction)?,
+                Color::from(*irradiance),
+            )),
+        })
+    }
+}
+
+/// Objects shared by all instances referencing the geometry by name
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GeometryDesc {
+    objects: Vec<ObjectDesc>,
+}
+
+/// A texture is either referenced by name, given as a plain color or gray value, or described
+/// inline.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TextureRef {
+    Named(String),
+    Color([f64; 3]),
+    Value(f64),
+    Inline(Box<TextureDesc>),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum TextureDesc {
+    Solid { color: [f64; 3] },
+    Checker { even: TextureRef, odd: TextureRef },
+    Noise { scale: f64 },
+    Image { path: PathBuf },
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MaterialRef {
+    Named(String),
+    Inline(Box<MaterialDesc>),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDesc {
+    Lambertian {
+        albedo: TextureRef,
+    },
+    Metal {
+        albedo: [f64; 3],
+        #[serde(default)]
+        fuzz: f64,
+    },
+    /// GGX conductor given by either a `metal` preset or its complex refraction index
+    Conductor {
+        metal: Option<ConductorPresetDesc>,
+        eta: Option<[f64; 3]>,
+        k: Option<[f64; 3]>,
+        roughness: Option<TextureRef>,
+    },
+    /// Smooth clear glass, unless a `roughness` or an `absorption` coefficient is given
+    Dielectric {
+        refraction_index: f64,
+        roughness: Option<TextureRef>,
+        absorption: Option<[f64; 3]>,
+    },
+    /// Disney's principled BSDF
+    Principled(Box<PrincipledDesc>),
+    DiffuseLight {
+        emit: TextureRef,
+    },
+    Isotropic {
+        albedo: TextureRef,
+    },
+}
+
+/// Unset parameters take the defaults of `Principled::new`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PrincipledDesc {
+    base_color: TextureRef,
+    metallic: Option<TextureRef>,
+    roughness: Option<TextureRef>,
+    specular: Option<TextureRef>,
+    specular_tint: Option<TextureRef>,
+    sheen: Option<TextureRef>,
+    clearcoat: Option<TextureRef>,
+    clearcoat_gloss: Option<TextureRef>,
+    transmission: Option<TextureRef>,
+    refraction_index: Option<f64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ConductorPresetDesc {
+    Gold,
+    Copper,
+    Aluminium,
+    Silver,
+}
+
+/// Step of a `transform` object, applied in the order given
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum TransformDesc {
+    Translate {
+        offset: [f64; 3],
+    },
+    /// Counter-clockwise rotation in degrees around `axis`
+    Rotate {
+        axis: [f64; 3],
+        angle: f64,
+    },
+    Scale {
+        factor: [f64; 3],
+    },
+    /// Row-major affine matrix
+    Matrix {
+        rows: [[f64; 4]; 4],
+    },
+}
+
+impl TransformDesc {
+    fn matrix(&self) -> Result<Matrix4<f64>, SceneError> {
+        Ok(match self {
+            TransformDesc::Translate { offset } => {
+                Matrix4::from_translation(Vector3::from(*offset))
+            }
+            TransformDesc::Rotate { axis, angle } => {
+                Matrix4::from_axis_angle(rotation_axis(*axis)?, Deg(*angle))
+            }
+            TransformDesc::Scale { factor: [x, y, z] } => {
+                Matrix4::from_nonuniform_scale(*x, *y, *z)
+            }
+            // cgmath matrices are column-major
+            TransformDesc::Matrix { rows } => Matrix4::from(*rows).transpose(),
+        })
+    }
+}
+
+/// Normalized `axis`, which must be finite and not zero
+fn rotation_axis(axis: [f64; 3]) -> Result<Vector3<f64>, SceneError> {
+    unit_direction(axis).ok_or(SceneError::InvalidTransform(
+        "rotation axis is zero or not finite",
+    ))
+}
+
+/// Normalized `direction`, `None` if it is zero or not finite
+fn unit_direction(direction: [f64; 3]) -> Option<Vector3<f64>> {
+    let direction = Vector3::from(direction);
+    let length = direction.magnitude();
+    (length > 0.0 && length.is_finite()).then(|| direction / length)
+}
+
+/// Placement of an `animated` object at `time`: scaled by `scale` (default 1), rotated
+/// counter-clockwise by `angle` degrees around `axis`, then moved by `translate` (default 0)
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyframeDesc {
+    time: f64,
+    translate: Option<[f64; 3]>,
+    rotate: Option<RotationDesc>,
+    scale: Option<[f64; 3]>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RotationDesc {
+    axis: [f64; 3],
+    angle: f64,
+}
+
+impl KeyframeDesc {
+    fn placement(&self) -> Result<Placement, SceneError> {
+        if !self.time.is_finite() {
+            return Err(SceneError::InvalidAnimation("keyframe time is not finite"));
+        }
+        let scale = Vector3::from(self.scale.unwrap_or([1.0, 1.0, 1.0]));
+        if scale.x * scale.y * scale.z == 0.0 {
+            return Err(SceneError::SingularTransform);
+        }
+        let rotation = match &self.rotate {
+            Some(rotate) => {
+                Quaternion::from_axis_angle(rotation_axis(rotate.axis)?, Deg(rotate.angle))
+            }
+            None => Quaternion::one(),
+        };
+        let placement = Placement {
+            translation: Vector3::from(self.translate.unwrap_or_default()),
+            rotation,
+            scale,
+        };
+        if !placement.matrix().is_finite() {
+            return Err(SceneError::InvalidTransform("keyframe is not finite"));
+        }
+        Ok(placement)
+    }
+}
+
+/// Product of `transforms`, the first one being applied first
+fn transform_matrix(transforms: &[TransformDesc]) -> Result<Matrix4<f64>, SceneError> {
+    let matrix = transforms
+        .iter()
+        .try_fold(Matrix4::identity(), |matrix, t| Ok(t.matrix()? * matrix))?;
+    if !matrix.is_finite() {
+        return Err(SceneError::InvalidTransform("matrix is not finite"));
+    }
+    if matrix.is_invertible() {
+        Ok(matrix)
+    } else {
+        Err(SceneError::SingularTransform)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ObjectDesc {
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+        material: MaterialRef,
+    },
+    MovingSphere {
+        center0: [f64; 3],
+        center1: [f64; 3],
+        time0: f64,
+        time1: f64,
+        radius: f64,
+        material: MaterialRef,
+    },
+    XyRect {
+        x0: f64,
+        x1: f64,
+        y0: f64,
+        y1: f64,
+        k: f64,
+        material: MaterialRef,
+    },
+    XzRect {
+        x0: f64,
+        x1: f64,
+        z0: f64,
+        z1: f64,
+        k: f64,
+        material: MaterialRef,
+    },
+    YzRect {
+        y0: f64,
+        y1: f64,
+        z0: f64,
+        z1: f64,
+        k: f64,
+        material: MaterialRef,
+    },
+    Cuboid {
+        p0: [f64; 3],
+        p1: [f64; 3],
+        material: MaterialRef,
+    },
+    RotateY {
+        angle: f64,
+        object: Box<ObjectDesc>,
+    },
+    Translate {
+        offset: [f64; 3],
+        object: Box<ObjectDesc>,
+    },
+    Transform {
+        transforms: Vec<TransformDesc>,
+        object: Box<ObjectDesc>,
+    },
+    /// `object` moved, turned and scaled by `keyframes`
+    Animated {
+        keyframes: Vec<KeyframeDesc>,
+        object: Box<ObjectDesc>,
+    },
+    /// Named geometry, placed by `transforms` and optionally painted with `material`
+    Instance {
+        geometry: String,
+        #[serde(default)]
+        transforms: Vec<TransformDesc>,
+        material: Option<MaterialRef>,
+    },
+    ConstantMedium {
+        density: f64,
+        albedo: TextureRef,
+        boundary: Box<ObjectDesc>,
+    },
+    List {
+        objects: Vec<ObjectDesc>,
+    },
+    Bvh {
+        objects: Vec<ObjectDesc>,
+    },
+    TriangleMesh {
+        positions: Vec<[f64; 3]>,
+        #[serde(default)]
+        normals: Vec<[f64; 3]>,
+        #[serde(default)]
+        uvs: Vec<[f64; 2]>,
+        indices: Vec<[usize; 3]>,
+        material: MaterialRef,
+    },
+    /// Wavefront OBJ file, using the materials of its MTL file unless `material` is given
+    Obj {
+        path: PathBuf,
+        material: Option<MaterialRef>,
+    },
+}
+
+/// Turns the parsed descriptions into hittables, resolving named textures, materials and
+/// geometries on first use. Each of them is built once and shared by all references, so e.g.
+/// an image texture is loaded once and a noise texture referenced twice uses the same
+/// permutation. Named geometries are built into a `Bvh` shared by all their instances.
+struct Builder<'a> {
+    desc: &'a SceneDesc,
+    base_dir: &'a Path,
+    rng: &'a mut dyn RngCore,
+    textures: HashMap<&'a str, Arc<dyn Texture>>,
+    materials: HashMap<&'a str, Arc<dyn Material>>,
+    geometries: HashMap<&'a str, Arc<dyn Hittable>>,
+    /// Named textures currently being built, to reject checkers containing themselves.
+    resolving: Vec<&'a str>,
+    /// Named geometries currently being built, to reject geometries instancing themselves.
+    instancing: Vec<&'a str>,
+    /// Time span nested acceleration structures are built for, the same as for the top-level
+    /// one, so they bound moving objects over everything that is rendered.
+    time0: f64,
+    time1: f64,
+    /// Strategy for `bvh` objects, the same as for the top-level BVH
+    split: BvhSplit,
+}
+
+impl<'a> Builder<'a> {
+    fn texture_ref(&mut self, texture: &'a TextureRef) -> Result<Arc<dyn Texture>, SceneError> {
+        match texture {
+            TextureRef::Named(name) => {
+                if let Some(texture) = self.textures.get(name.as_str()) {
+                    return Ok(texture.clone());
+                }
+                let desc = self
+                    .desc
+                    .textures
+                    .get(name)
+                    .ok_or_else(|| SceneError::UnknownTexture(name.clone()))?;
+                if self.resolving.contains(&name.as_str()) {
+                    return Err(SceneError::Recursive(name.clone()));
+                }
+                self.resolving.push(name);
+                let texture = self.texture(desc)?;
+                self.resolving.pop();
+                self.textures.insert(name, texture.clone());
+                Ok(texture)
+            }
+            TextureRef::Color(color) => Ok(Arc::new(SolidColor::new(&Color::from(*color)))),
+            TextureRef::Value(value) => Ok(Arc::new(SolidColor::new(&Color::new(
+                *value, *value, *value,
+            )))),
+            TextureRef::Inline(desc) => self.texture(desc),
+        }
+    }
+
+    fn texture(&mut self, desc: &'a TextureDesc) -> Result<Arc<dyn Texture>, SceneError> {
+        Ok(match desc {
+            TextureDesc::Solid { color } => Arc::new(SolidColor::new(&Color::from(*color))),
+            TextureDesc::Checker { even, odd } => Arc::new(CheckerTexture::new(
+                self.texture_ref(even)?,
+                self.texture_ref(odd)?,
+            )),
+            TextureDesc::Noise { scale } => Arc::new(NoiseTexture::new(*scale, &mut *self.rng)),
+            TextureDesc::Image { path } => {
+                let path = self.base_dir.join(path);
+                Arc::new(ImageTexture::open(&path).map_err(|err| SceneError::Image(path, err))?)
+            }
+        })
+    }
+
+    fn material_ref(&mut self, material: &'a MaterialRef) -> Result<Arc<dyn Material>, SceneError> {
+        match material {
+            MaterialRef::Named(name) => {
+                if let Some(material) = self.materials.get(name.as_str()) {
+                    return Ok(material.clone());
+                }
+                let desc = self
+                    .desc
+                    .materials
+                    .get(name)
+                    .ok_or_else(|| SceneError::UnknownMaterial(name.clone()))?;
+                let material = self.material(desc)?;
+                self.materials.insert(name, material.clone());
+                Ok(material)
+            }
+            MaterialRef::Inline(desc) => self.material(desc),
+        }
+    }
+
+    fn material(&mut self, desc: &'a MaterialDesc) -> Result<Arc<dyn Material>, SceneError> {
+        Ok(match desc {
+            MaterialDesc::Lambertian { albedo } => {
+                Arc::new(Lambertian::new(self.texture_ref(albedo)?))
+            }
+            MaterialDesc::Metal { albedo, fuzz } => {
+                Arc::new(Metal::new(&Color::from(*albedo), *fuzz))
+            }
+            MaterialDesc::Conductor {
+                metal,
+                eta,
+                k,
+                roughness,
+            } => {
+                let roughness = match roughness {
+                    Some(roughness) => self.texture_ref(roughness)?,
+                    None => Arc::new(SolidColor::new(&color::black())),
+                };
+                match (metal, eta, k) {
+                    (Some(metal), None, None) => {
+                        let preset = match metal {
+                            ConductorPresetDesc::Gold => ConductorPreset::Gold,
+                            ConductorPresetDesc::Copper => ConductorPreset::Copper,
+                            ConductorPresetDesc::Aluminium => ConductorPreset::Aluminium,
+                            ConductorPresetDesc::Silver => ConductorPreset::Silver,
+                        };
+                        Arc::new(Conductor::preset(preset, roughness))
+                    }
+                    (None, Some(eta), Some(k)) => Arc::new(Conductor::new(
+                        Color::from(*eta),
+                        Color::from(*k),
+                        roughness,
+                    )),
+                    _ => {
+                        return Err(SceneError::InvalidMaterial(
+                            "conductor requires either `metal` or both `eta` and `k`",
+                        ))
+                    }
+                }
+            }
+            MaterialDesc::Dielectric {
+                refraction_index,
+                roughness: None,
+                absorption: None,
+            } => Arc::new(Dielectric::new(*refraction_index)),
+            MaterialDesc::Dielectric {
+                refraction_index,
+                roughness,
+                absorption,
+            } => {
+                let roughness = match roughness {
+                    Some(roughness) => self.texture_ref(roughness)?,
+                    None => Arc::new(SolidColor::new(&color::black())),
+                };
+                let absorption = absorption.map(Color::from).unwrap_or_else(color::black);
+                Arc::new(RoughDielectric::new(
+                    *refraction_index,
+                    roughness,
+                    absorption,
+                ))
+            }
+            MaterialDesc::Principled(principled) => {
+                let PrincipledDesc {
+                    base_color,
+                    metallic,
+                    roughness,
+                    specular,
+                    specular_tint,
+                    sheen,
+                    clearcoat,
+                    clearcoat_gloss,
+                    transmission,
+                    refraction_index,
+                } = principled.as_ref();
+                let mut principled = Principled::new(self.texture_ref(base_color)?);
+                let parameters = [
+                    (metallic, &mut principled.metallic),
+                    (roughness, &mut principled.roughness),
+                    (specular, &mut principled.specular),
+                    (specular_tint, &mut principled.specular_tint),
+                    (sheen, &mut principled.sheen),
+                    (clearcoat, &mut principled.clearcoat),
+                    (clearcoat_gloss, &mut principled.clearcoat_gloss),
+                    (transmission, &mut principled.transmission),
+                ];
+                for (desc, parameter) in parameters {
+                    if let Some(desc) = desc {
+                        *parameter = self.texture_ref(desc)?;
+                    }
+                }
+                if let Some(refraction_index) = refraction_index {
+                    principled.refraction_index = *refraction_index;
+                }
+                Arc::new(principled)
+            }
+            MaterialDesc::DiffuseLight { emit } => {
+                Arc::new(DiffuseLight::new(self.texture_ref(emit)?))
+            }
+            MaterialDesc::Isotropic { albedo } => {
+                Arc::new(Isotropic::new(self.texture_ref(albedo)?))
+            }
+        })
+    }
+
+    fn object(&mut self, desc: &'a ObjectDesc) -> Result<Box<dyn Hittable>, SceneError> {
+        Ok(match desc {
+            ObjectDesc::Sphere {
+                center,
+                radius,
+                material,
+            } => Box::new(Sphere::new(
+                Point3::from(*center),
+                *radius,
+                self.material_ref(material)?,
+            )),
+            ObjectDesc::MovingSphere {
+                center0,
+                center1,
+                time0,
+                time1,
+                radius,
+                material,
+            } => Box::new(MovingSphere::new(
+                Point3::from(*center0),
+                Point3::from(*center1),
+                *time0,
+                *time1,
+                *radius,
+                self.material_ref(material)?,
+            )),
+            ObjectDesc::XyRect {
+                x0,
+                x1,
+                y0,
+                y1,
+                k,
+                material,
+            } => Box::new(XYRect {
+                material: self.material_ref(material)?,
+                x0: *x0,
+                x1: *x1,
+                y0: *y0,
+                y1: *y1,
+                k: *k,
+            }),
+            ObjectDesc::XzRect {
+                x0,
+                x1,
+                z0,
+                z1,
+                k,
+                material,
+            } => Box::new(XZRect {
+                material: self.material_ref(material)?,
+                x0: *x0,
+                x1: *x1,
+                z0: *z0,
+                z1: *z1,
+                k: *k,
+            }),
+            ObjectDesc::YzRect {
+                y0,
+                y1,
+                z0,
+                z1,
+                k,
+                material,
+            } => Box::new(YZRect {
+                material: self.material_ref(material)?,
+                y0: *y0,
+                y1: *y1,
+                z0: *z0,
+                z1: *z1,
+                k: *k,
+            }),
+            ObjectDesc::Cuboid { p0, p1, material } => Box::new(Cuboid::new(
+                Point3::from(*p0),
+                Point3::from(*p1),
+                self.material_ref(material)?,
+            )),
+            ObjectDesc::RotateY { angle, object } => {
+                Box::new(Transform::new(self.object(object)?).rotate_y(*angle))
+            }
+            ObjectDesc::Translate { offset, object } => {
+                Box::new(Transform::new(self.object(object)?).translate(Vector3::from(*offset)))
+            }
+            ObjectDesc::Animated { keyframes, object } => {
+                let keyframes = Keyframes::new(
+                    keyframes
+                        .iter()
+                        .map(|key| Ok((key.time, key.placement()?)))
+                        .collect::<Result<_, SceneError>>()?,
+                )
+                .ok_or(SceneError::InvalidAnimation("no keyframes"))?;
+                Box::new(Animated::new(self.object(object)?, keyframes))
+            }
+            ObjectDesc::Transform { transforms, object } => Box::new(Transform::with_matrix(
+                self.object(object)?,
+                transform_matrix(transforms)?,
+            )),
+            ObjectDesc::Instance {
+                geometry,
+                transforms,
+                material,
+            } => {
+                let instance =
+                    Instance::new(self.geometry(geometry)?, transform_matrix(transforms)?);
+                Box::new(match material {
+                    Some(material) => instance.with_material(self.material_ref(material)?),
+                    None => instance,
+                })
+            }
+            ObjectDesc::ConstantMedium {
+                density,
+                albedo,
+                boundary,
+            } => Box::new(ConstantMedium::new(
+                self.object(boundary)?,
+                *density,
+                self.texture_ref(albedo)?,
+            )),
+            ObjectDesc::List { objects } => {
+                if objects.is_empty() {
+                    return Err(SceneError::EmptyList);
+                }
+                Box::new(HitableList {
+                    objects: self.objects(objects)?,
+                })
+            }
+            ObjectDesc::Bvh { objects } => {
+                if objects.is_empty() {
+                    return Err(SceneError::EmptyBvh);
+                }
+                let objects = self.objects(objects)?;
+                Box::new(Bvh::with_split(objects, self.time0, self.time1, self.split))
+            }
+            ObjectDesc::TriangleMesh {
+                positions,
+                normals,
+                uvs,
+                indices,
+                material,
+            } => {
+                if indices.is_empty() {
+                    return Err(SceneError::InvalidMesh("no triangles"));
+                }
+                if indices.iter().flatten().any(|i| *i >= positions.len()) {
+                    return Err(SceneError::InvalidMesh("index out of range"));
+                }
+                if !normals.is_empty() && normals.len() != positions.len() {
+                    return Err(SceneError::InvalidMesh("one normal per position required"));
+                }
+                if !uvs.is_empty() && uvs.len() != positions.len() {
+                    return Err(SceneError::InvalidMesh("one uv per position required"));
+                }
+                let mesh = Mesh {
+                    positions: positions.iter().map(|p| Point3::from(*p)).collect(),
+                    normals: normals.iter().map(|n| Vector3::from(*n)).collect(),
+                    uvs: uvs.iter().map(|uv| (uv[0], uv[1])).collect(),
+                    indices: indices.clone(),
+                    material: self.material_ref(material)?,
+                };
+                Box::new(TriangleMesh::new(mesh, self.time0, self.time1))
+            }
+            ObjectDesc::Obj { path, material } => {
+                let material = match material {
+                    Some(material) => Some(self.material_ref(material)?),
+                    None => None,
+                };
+                let path = self.base_dir.join(path);
+                Box::new(
+                    obj::load(&path, material, self.time0, self.time1)
+                        .map_err(|err| SceneError::Obj(path, err))?,
+                )
+            }
+        })
+    }
+
+    fn geometry(&mut self, name: &'a str) -> Result<Arc<dyn Hittable>, SceneError> {
+        if let Some(geometry) = self.geometries.get(name) {
+            return Ok(geometry.clone());
+        }
+        let desc = self
+            .desc
+            .geometries
+            .get(name)
+            .ok_or_else(|| SceneError::UnknownGeometry(name.to_string()))?;
+        if desc.objects.is_empty() {
+            return Err(SceneError::EmptyBvh);
+        }
+        if self.instancing.contains(&name) {
+            return Err(SceneError::Recursive(name.to_string()));
+        }
+        self.instancing.push(name);
+        let objects = self.objects(&desc.objects)?;
+        self.instancing.pop();
+        let geometry: Arc<dyn Hittable> = Arc::new(Bvh::with_split(
+            objects,
+            self.time0,
+            self.time1,
+            BvhSplit::default(),
+        ));
+        self.geometries.insert(name, geometry.clone());
+        Ok(geometry)
+    }
+
+    fn objects(&mut self, desc: &'a [ObjectDesc]) -> Result<Vec<Box<dyn Hittable>>, SceneError> {
+        desc.iter().map(|object| self.object(object)).collect()
+    }
+}
+
+/// Load a scene description file (TOML). Relative image paths are resolved against the
+/// directory containing the scene file. Acceleration structures are built for the time span
+/// `timeline` to be rendered, the shutter of the camera if not given. `bvh` objects are split
+/// by `split`.
+pub fn load<P: AsRef<Path>>(
+    path: P,
+    timeline: Option<(f64, f64)>,
+    split: BvhSplit,
+    rng: &mut dyn RngCore,
+) -> Result<Scene, SceneError> {
+    let path = path.as_ref();
+    let content = fs::read_to_string(path).map_err(|err| SceneError::Io(path.into(), err))?;
+    let desc: SceneDesc = toml::from_str(&content).map_err(SceneError::Parse)?;
+    for key in &desc.camera.keyframes {
+        if !key.time.is_finite() {
+            return Err(SceneError::InvalidCamera("keyframe time is not finite"));
+        }
+    }
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let (time0, time1) = timeline.unwrap_or((desc.camera.time0, desc.camera.time1));
+
+    let mut builder = Builder {
+        desc: &desc,
+        base_dir,
+        rng,
+        textures: HashMap::new(),
+        materials: HashMap::new(),
+        geometries: HashMap::new(),
+        resolving: vec![],
+        instancing: vec![],
+        time0,
+        time1,
+        split,
+    };
+    let objects = builder.objects(&desc.objects)?;
+    let environment: Box<dyn Environment> = match &desc.environment {
+        Some(EnvironmentDesc::Image {
+            path,
+            rotation,
+            intensity,
+        }) => {
+            let path = base_dir.join(path);
+            Box::new(
+                EnvironmentMap::open(&path, *rotation, intensity.unwrap_or(1.0))
+                    .map_err(|err| SceneError::Image(path, err))?,
+            )
+        }
+        Some(EnvironmentDesc::Sky {
+            sun_direction,
+            turbidity,
+            intensity,
+        }) => Box::new(Sky::new(
+            unit_direction(*sun_direction).ok_or(SceneError::InvalidEnvironment(
+                "sun direction is zero or not finite",
+            ))?,
+            turbidity.unwrap_or(3.0),
+            intensity.unwrap_or(1.0),
+        )),
+        None => Box::new(Uniform::new(Color::from(desc.background))),
+    };
+
+    let mut camera = desc.camera.clone();
+    if let ApertureShape::Image { path } = &mut camera.aperture_shape {
+        *path = base_dir.join(&*path);
+    }
+
+    Ok(Scene {
+        world: HitableList { objects },
+        camera,
+        lights: desc
+            .lights
+            .iter()
+            .map(LightDesc::light)
+            .collect::<Result<_, _>>()?,
+        environment,
+    })
+}
@@ -0,0 +1,217 @@
+use crate::{
+    color::{self, Color},
+    environment::Environment,
+    onb::Onb,
+};
+use cgmath::{InnerSpace, Vector3};
+use rand::{Rng, RngCore};
+use raytracer::random_unit_vector;
+use std::f64::consts::PI;
+
+/// Luminance represented by a radiance of 1, in kcd/m². It exposes a white surface in full
+/// sun at about 1.
+const UNIT_LUMINANCE: f64 = 20.0;
+
+/// Luminance of the sun outside the atmosphere, in kcd/m²
+const SUN_LUMINANCE: f64 = 1.96e6;
+
+/// Angular radius of the sun in radians
+const SUN_RADIUS: f64 = 0.004_65;
+
+/// Probability of sampling the sun disk rather than the whole sky, while the sun is up
+const SUN_PROBABILITY: f64 = 0.5;
+
+/// Perez et al. luminance distribution, relative to the zenith
+struct Perez {
+    a: f64,
+    b: f64,
+    c: f64,
+    d: f64,
+    e: f64,
+}
+
+impl Perez {
+    fn new(turbidity: f64, coefficients: [[f64; 2]; 5]) -> Self {
+        let [a, b, c, d, e] = coefficients.map(|[t, c]| t * turbidity + c);
+        Self { a, b, c, d, e }
+    }
+
+    /// `cos_theta` is the cosine of the zenith angle, `gamma` the angle to the sun.
+    fn f(&self, cos_theta: f64, gamma: f64) -> f64 {
+        (1.0 + self.a * (self.b / cos_theta).exp())
+            * (1.0 + self.c * (self.d * gamma).exp() + self.e * gamma.cos().powi(2))
+    }
+}
+
+/// Clear sky after Preetham et al. 1999, "A Practical Analytic Model for Daylight", with the
+/// sun as a disk of its true angular size. The sun is attenuated by Rayleigh and aerosol
+/// scattering along its path through the atmosphere. Below the horizon, the sky keeps its
+/// horizon color.
+pub struct Sky {
+    /// Unit vector towards the sun
+    sun: Vector3<f64>,
+    /// Perez distributions of the luminance Y and the chromaticities x and y
+    perez: [Perez; 3],
+    /// Zenith values of Y, x and y, divided by the Perez distributions at the zenith
+    zenith: [f64; 3],
+    sun_radiance: Color,
+    /// `radiance` is scaled by it
+    intensity: f64,
+}
+
+impl Sky {
+    /// `turbidity` measures the haze, from 2 for a very clear sky to 10 for a hazy one.
+    pub fn new(sun: Vector3<f64>, turbidity: f64, intensity: f64) -> Self {
+        let sun = sun.normalize();
+        let t = turbidity.clamp(1.7, 10.0);
+        // The model only covers the sun above the horizon
+        let theta_s = sun.y.clamp(0.0, 1.0).acos();
+
+        let perez = [
+            Perez::new(
+                t,
+                [
+                    [0.1787, -1.4630],
+                    [-0.3554, 0.4275],
+                    [-0.0227, 5.3251],
+                    [0.1206, -2.5771],
+                    [-0.0670, 0.3703],
+                ],
+            ),
+            Perez::new(
+                t,
+                [
+                    [-0.0193, -0.2592],
+                    [-0.0665, 0.0008],
+                    [-0.0004, 0.2125],
+                    [-0.0641, -0.8989],
+                    [-0.0033, 0.0452],
+                ],
+            ),
+            Perez::new(
+                t,
+                [
+                    [-0.0167, -0.2608],
+                    [-0.0950, 0.0092],
+                    [-0.0079, 0.2102],
+                    [-0.0441, -1.6537],
+                    [-0.0109, 0.0529],
+                ],
+            ),
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let chromaticity = |m: [[f64; 4]; 3]| {
+            let tv = [t * t, t, 1.0];
+            let sv = [theta_s.powi(3), theta_s * theta_s, theta_s, 1.0];
+            (0..3)
+                .map(|i| tv[i] * (0..4).map(|j| m[i][j] * sv[j]).sum::<f64>())
+                .sum::<f64>()
+        };
+        let zenith_x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+        let zenith = [zenith_luminance, zenith_x, zenith_y];
+        let zenith = [0, 1, 2].map(|i| zenith[i] / perez[i].f(1.0, theta_s));
+
+        Self {
+            sun,
+            perez,
+            zenith,
+            sun_radiance: Self::sun_transmittance(sun, t) * (SUN_LUMINANCE / UNIT_LUMINANCE),
+            intensity,
+        }
+    }
+
+    /// Fraction of sunlight reaching the ground at 650, 550 and 450 nm, zero once the sun has
+    /// set.
+    fn sun_transmittance(sun: Vector3<f64>, turbidity: f64) -> Color {
+        if sun.y <= 0.0 {
+            return color::black();
+        }
+        // Relative optical air mass (Kasten and Young 1989)
+        let zenith_angle = sun.y.acos().to_degrees();
+        let air_mass = 1.0 / (sun.y + 0.50572 * (96.07995 - zenith_angle).powf(-1.6364));
+        // Angstrom's turbidity coefficient
+        let beta = 0.04608 * turbidity - 0.04586;
+        let channel = |wavelength: f64| {
+            let rayleigh = 0.008735 * wavelength.powf(-4.08);
+            let aerosol = beta * wavelength.powf(-1.3);
+            (-air_mass * (rayleigh + aerosol)).exp()
+        };
+        Color::new(channel(0.65), channel(0.55), channel(0.45))
+    }
+
+    fn sun_is_up(&self) -> bool {
+        self.sun.y > 0.0
+    }
+
+    fn sky_radiance(&self, direction: Vector3<f64>) -> Color {
+        let cos_theta = direction.y.max(0.0);
+        let gamma = direction.dot(self.sun).clamp(-1.0, 1.0).acos();
+        let [luminance, x, y] =
+            [0, 1, 2].map(|i| self.zenith[i] * self.perez[i].f(cos_theta, gamma));
+        if y <= 0.0 {
+            return color::black();
+        }
+        // xyY to XYZ to linear sRGB
+        let big_x = x * luminance / y;
+        let big_z = (1.0 - x - y) * luminance / y;
+        let rgb = Color::new(
+            3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z,
+            -0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z,
+            0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z,
+        );
+        rgb.map(|c| c.max(0.0) / UNIT_LUMINANCE)
+    }
+}
+
+impl Environment for Sky {
+    fn radiance(&self, direction: Vector3<f64>) -> Color {
+        let mut radiance = self.sky_radiance(direction);
+        if self.sun_is_up() && direction.dot(self.sun) >= SUN_RADIUS.cos() {
+            radiance += self.sun_radiance;
+        }
+        radiance * self.intensity
+    }
+
+    fn is_light(&self) -> bool {
+        true
+    }
+
+    /// Uniform in the sun disk or on the whole sphere.
+    fn random(&self, rng: &mut dyn RngCore) -> Vector3<f64> {
+        if !self.sun_is_up() || rng.gen_range(0.0..1.0) >= SUN_PROBABILITY {
+            return random_unit_vector(rng);
+        }
+        let cos_theta = 1.0 - rng.gen_range(0.0..1.0) * (1.0 - SUN_RADIUS.cos());
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let phi = 2.0 * PI * rng.gen_range(0.0..1.0);
+        Onb::from_w(self.sun).local(Vector3::new(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            cos_theta,
+        ))
+    }
+
+    fn pdf_value(&self, direction: Vector3<f64>) -> f64 {
+        let uniform = 1.0 / (4.0 * PI);
+        if !self.sun_is_up() {
+            return uniform;
+        }
+        let sun = if direction.dot(self.sun) >= SUN_RADIUS.cos() {
+            1.0 / (2.0 * PI * (1.0 - SUN_RADIUS.cos()))
+        } else {
+            0.0
+        };
+        SUN_PROBABILITY * sun + (1.0 - SUN_PROBABILITY) * uniform
+    }
+}
@@ -1,7 +1,9 @@
-use crate::{aabb::Aabb, hitable::*, material::Material, ray::Ray};
+use crate::{aabb::Aabb, hitable::*, material::Material, onb::Onb, ray::Ray};
 use cgmath::*;
-use std::f64::consts::PI;
+use rand::{Rng, RngCore};
+use raytracer::random_unit_vector;
 use std::option::Option;
+use std::{f64::consts::PI, sync::Arc};
 
 trait SphereFunc {
     // p: a given point on the sphere of radius one, centered at the origin.
@@ -18,16 +20,30 @@ trait SphereFunc {
     }
 }
 
+/// Random direction within the cone subtended by a sphere of `radius` at `distance_squared`,
+/// given relative to the z axis pointing at the sphere's center.
+fn random_to_sphere(radius: f64, distance_squared: f64, rng: &mut dyn RngCore) -> Vector3<f64> {
+    let r1: f64 = rng.gen_range(0.0..1.0);
+    let r2: f64 = rng.gen_range(0.0..1.0);
+    let z = 1.0 + r2 * ((1.0 - radius * radius / distance_squared).sqrt() - 1.0);
+
+    let phi = 2.0 * PI * r1;
+    let x = phi.cos() * (1.0 - z * z).sqrt();
+    let y = phi.sin() * (1.0 - z * z).sqrt();
+
+    Vector3::new(x, y, z)
+}
+
 pub struct Sphere {
     center: Point3<f64>,
     radius: f64,
-    material: Box<dyn Material>,
+    material: Arc<dyn Material>,
 }
 
 impl SphereFunc for Sphere {}
 
 impl Sphere {
-    pub fn new(center: Point3<f64>, radius: f64, material: Box<dyn Material>) -> Self {
+    pub fn new(center: Point3<f64>, radius: f64, material: Arc<dyn Material>) -> Self {
         Self {
             center,
             radius,
@@ -37,7 +53,7 @@ impl Sphere {
 }
 
 impl Hittable for Sphere {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let oc = ray.origin() - self.center;
         let a = ray.direction().magnitude2();
         let half_b = oc.dot(ray.direction());
@@ -79,6 +95,40 @@ impl Hittable for Sphere {
             self.center + Vector3::new(self.radius, self.radius, self.radius),
         ))
     }
+
+    fn pdf_value(&self, origin: Point3<f64>, direction: Vector3<f64>) -> f64 {
+        let record = match self.hit(&Ray::new(origin, direction, 0.0), 0.001, f64::MAX) {
+            Some(record) => record,
+            None => return 0.0,
+        };
+        let radius_squared = self.radius * self.radius;
+        let distance_squared = (self.center - origin).magnitude2();
+        if distance_squared <= radius_squared {
+            // Seen from inside, the sphere is sampled uniformly by area
+            let area = 4.0 * PI * radius_squared;
+            let cosine = (direction.dot(record.normal) / direction.magnitude()).abs();
+            record.t * record.t * direction.magnitude2() / (cosine * area)
+        } else {
+            let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+            let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
+            1.0 / solid_angle
+        }
+    }
+
+    fn random(&self, origin: Point3<f64>, rng: &mut dyn RngCore) -> Vector3<f64> {
+        let direction = self.center - origin;
+        let distance_squared = direction.magnitude2();
+        if distance_squared <= self.radius * self.radius {
+            return self.center + self.radius * random_unit_vector(rng) - origin;
+        }
+        Onb::from_w(direction).local(random_to_sphere(self.radius, distance_squared, rng))
+    }
+
+    fn lights<'a>(&'a self, lights: &mut Vec<Emitter<'a>>) {
+        if self.material.is_emissive() {
+            lights.push(Emitter::new(self));
+        }
+    }
 }
 
 pub struct MovingSphere {
@@ -87,7 +137,7 @@ pub struct MovingSphere {
     time0: f64,
     time1: f64,
     radius: f64,
-    material: Box<dyn Material>,
+    material: Arc<dyn Material>,
 }
 
 impl SphereFunc for MovingSphere {}
@@ -99,7 +149,7 @@ impl MovingSphere {
         time0: f64,
         time1: f64,
         radius: f64,
-        material: Box<dyn Material>,
+        material: Arc<dyn Material>,
     ) -> Self {
         Self {
             center0,
@@ -118,7 +168,7 @@ impl MovingSphere {
 }
 
 impl Hittable for MovingSphere {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let oc = ray.origin() - self.center(ray.time());
         let a = ray.direction().magnitude2();
         let half_b = oc.dot(ray.direction());
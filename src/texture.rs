@@ -3,20 +3,20 @@ use crate::{
     perlin::Perlin,
 };
 use cgmath::Point3;
+use image::ImageResult;
 use rand::RngCore;
+use std::{path::Path, sync::Arc};
 
 pub trait Texture: Send + Sync {
     fn value(&self, u: f64, v: f64, p: &Point3<f64>) -> Color;
-    fn box_clone(&self) -> Box<dyn Texture>;
 }
 
-impl Clone for Box<dyn Texture> {
-    fn clone(&self) -> Box<dyn Texture> {
-        self.box_clone()
+impl<T: Texture + ?Sized> Texture for Arc<T> {
+    fn value(&self, u: f64, v: f64, p: &Point3<f64>) -> Color {
+        self.as_ref().value(u, v, p)
     }
 }
 
-#[derive(Clone)]
 pub struct SolidColor {
     color: Color,
 }
@@ -31,20 +31,15 @@ impl Texture for SolidColor {
     fn value(&self, _: f64, _: f64, _: &Point3<f64>) -> Color {
         self.color
     }
-
-    fn box_clone(&self) -> Box<dyn Texture> {
-        Box::new((*self).clone())
-    }
 }
 
-#[derive(Clone)]
 pub struct CheckerTexture {
-    odd: Box<dyn Texture>,
-    even: Box<dyn Texture>,
+    odd: Arc<dyn Texture>,
+    even: Arc<dyn Texture>,
 }
 
 impl CheckerTexture {
-    pub fn new(even: Box<dyn Texture>, odd: Box<dyn Texture>) -> Self {
+    pub fn new(even: Arc<dyn Texture>, odd: Arc<dyn Texture>) -> Self {
         Self { odd, even }
     }
 }
@@ -58,13 +53,8 @@ impl Texture for CheckerTexture {
             self.even.value(u, v, p)
         }
     }
-
-    fn box_clone(&self) -> Box<dyn Texture> {
-        Box::new((*self).clone())
-    }
 }
 
-#[derive(Clone)]
 pub struct NoiseTexture {
     noise: Perlin,
     scale: f64,
@@ -85,15 +75,10 @@ impl Texture for NoiseTexture {
             * 0.5
             * (1.0 + f64::sin(self.scale * p.z + 10.0 * self.noise.turb(&(self.scale * *p), 7)))
     }
-
-    fn box_clone(&self) -> Box<dyn Texture> {
-        Box::new((*self).clone())
-    }
 }
 
 const BYTES_PER_PIXEL: usize = 3;
 
-#[derive(Clone)]
 pub struct ImageTexture {
     data: Vec<u8>,
     width: usize,
@@ -108,6 +93,12 @@ impl ImageTexture {
             height,
         }
     }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> ImageResult<Self> {
+        let image = image::open(path)?.to_rgb8();
+        let (width, height) = image.dimensions();
+        Ok(Self::new(image.into_raw(), width as usize, height as usize))
+    }
 }
 
 impl Texture for ImageTexture {
@@ -136,8 +127,4 @@ impl Texture for ImageTexture {
 
         Color::new(r, g, b)
     }
-
-    fn box_clone(&self) -> Box<dyn Texture> {
-        Box::new((*self).clone())
-    }
 }
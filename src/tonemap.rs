@@ -0,0 +1,70 @@
+use crate::color::Color;
+
+/// Tone mapping operator compressing scene radiance into the displayable [0,1] range.
+/// Operators are applied to each color channel separately.
+#[derive(Clone, Copy)]
+pub enum Operator {
+    /// Cut off everything above 1
+    Clamp,
+    /// `x / (1 + x)`
+    Reinhard,
+    /// Reinhard scaled so that `white` maps to 1
+    ExtendedReinhard { white: f64 },
+    /// Krzysztof Narkowicz's fit of the ACES filmic curve
+    Aces,
+    /// John Hable's filmic curve from Uncharted 2
+    Hable,
+}
+
+impl Operator {
+    fn apply(&self, x: f64) -> f64 {
+        match *self {
+            Operator::Clamp => x,
+            Operator::Reinhard => x / (1.0 + x),
+            Operator::ExtendedReinhard { white } => x * (1.0 + x / (white * white)) / (1.0 + x),
+            Operator::Aces => {
+                let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+                x * (a * x + b) / (x * (c * x + d) + e)
+            }
+            Operator::Hable => {
+                const EXPOSURE_BIAS: f64 = 2.0;
+                const WHITE: f64 = 11.2;
+                hable(EXPOSURE_BIAS * x) / hable(WHITE)
+            }
+        }
+    }
+}
+
+fn hable(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+}
+
+/// The sRGB opto-electronic transfer function, encoding linear [0,1] values.
+pub fn srgb_oetf(x: f64) -> f64 {
+    if x <= 0.0031308 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Display pipeline turning linear radiance into 8-bit sRGB colors: exposure, tone mapping,
+/// sRGB encoding and quantization.
+#[derive(Clone, Copy)]
+pub struct ToneMapping {
+    /// Exposure adjustment in stops
+    pub exposure: f64,
+    pub operator: Operator,
+}
+
+impl ToneMapping {
+    pub fn encode(&self, color: Color) -> [u8; 3] {
+        let scale = self.exposure.exp2();
+        let channel = |x: f64| {
+            let mapped = self.operator.apply((x * scale).max(0.0));
+            (255.0 * srgb_oetf(mapped.clamp(0.0, 1.0))).round() as u8
+        };
+        [channel(color.x), channel(color.y), channel(color.z)]
+    }
+}
@@ -0,0 +1,409 @@
+use crate::{
+    aabb::Aabb,
+    animation::{Interpolate, Keyframes},
+    hitable::*,
+    ray::Ray,
+};
+use cgmath::{
+    Deg, ElementWise, EuclideanSpace, InnerSpace, Matrix, Matrix4, Point3, Quaternion,
+    SquareMatrix, Transform as _, Vector3,
+};
+use std::iter;
+
+/// Places a hittable by an affine transformation from its local space into world space.
+/// Rays are transformed into local space by the inverse matrix; normals are transformed back
+/// by the inverse transpose, so non-uniform scaling keeps them perpendicular to the surface.
+pub struct Transform<H: Hittable> {
+    hitable: H,
+    matrix: Matrix4<f64>,
+    inverse: Matrix4<f64>,
+    normal_matrix: Matrix4<f64>,
+}
+
+impl<H: Hittable> Transform<H> {
+    /// Identity transformation, to be extended by the methods below.
+    pub fn new(hitable: H) -> Self {
+        Self::with_matrix(hitable, Matrix4::identity())
+    }
+
+    /// Panics if `matrix` is not invertible.
+    pub fn with_matrix(hitable: H, matrix: Matrix4<f64>) -> Self {
+        let inverse = matrix
+            .invert()
+            .expect("transformation matrix is not invertible");
+        Self {
+            hitable,
+            matrix,
+            inverse,
+            normal_matrix: inverse.transpose(),
+        }
+    }
+
+    /// Apply `matrix` after the current transformation.
+    pub fn then(self, matrix: Matrix4<f64>) -> Self {
+        let matrix = matrix * self.matrix;
+        Self::with_matrix(self.hitable, matrix)
+    }
+
+    pub fn rotate_y(self, angle: f64) -> Self {
+        self.then(Matrix4::from_angle_y(Deg(angle)))
+    }
+
+    pub fn translate(self, offset: Vector3<f64>) -> Self {
+        self.then(Matrix4::from_translation(offset))
+    }
+}
+
+impl<H: Hittable> Hittable for Transform<H> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        hit_transformed(
+            &self.hitable,
+            ray,
+            t_min,
+            t_max,
+            &self.matrix,
+            &self.inverse,
+            &self.normal_matrix,
+        )
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.hitable
+            .bounding_box(time0, time1)
+            .map(|bbox| transform_box(&bbox, &self.matrix))
+    }
+
+    fn lights<'a>(&'a self, lights: &mut Vec<Emitter<'a>>) {
+        lights_transformed(&self.hitable, lights, &self.matrix, &self.inverse);
+    }
+}
+
+/// Hit `hitable` placed by `matrix`, whose inverse and inverse transpose are given.
+fn hit_transformed<'a, H: Hittable>(
+    hitable: &'a H,
+    ray: &Ray,
+    t_min: f64,
+    t_max: f64,
+    matrix: &Matrix4<f64>,
+    inverse: &Matrix4<f64>,
+    normal_matrix: &Matrix4<f64>,
+) -> Option<HitRecord<'a>> {
+    // The direction is not normalized, so `t` is the same in both spaces.
+    let local_ray = Ray::new(
+        inverse.transform_point(ray.origin()),
+        inverse.transform_vector(ray.direction()),
+        ray.time(),
+    );
+    hitable.hit(&local_ray, t_min, t_max).map(|mut record| {
+        record.p = matrix.transform_point(record.p);
+        // The normal keeps its orientation towards the ray, `front_face` stays valid.
+        record.normal = normal_matrix.transform_vector(record.normal).normalize();
+        record
+    })
+}
+
+/// Collect the lights of `hitable` placed by `matrix`, whose inverse is given.
+fn lights_transformed<'a, H: Hittable>(
+    hitable: &'a H,
+    lights: &mut Vec<Emitter<'a>>,
+    matrix: &Matrix4<f64>,
+    inverse: &Matrix4<f64>,
+) {
+    let start = lights.len();
+    hitable.lights(lights);
+    for light in &mut lights[start..] {
+        *light = light.placed(matrix, inverse);
+    }
+}
+
+fn corners(bbox: &Aabb) -> impl Iterator<Item = Point3<f64>> + '_ {
+    (0..8).map(move |i| {
+        Point3::new(
+            if i & 1 == 0 {
+                bbox.min().x
+            } else {
+                bbox.max().x
+            },
+            if i & 2 == 0 {
+                bbox.min().y
+            } else {
+                bbox.max().y
+            },
+            if i & 4 == 0 {
+                bbox.min().z
+            } else {
+                bbox.max().z
+            },
+        )
+    })
+}
+
+/// Box around `bbox` transformed by `matrix`
+fn transform_box(bbox: &Aabb, matrix: &Matrix4<f64>) -> Aabb {
+    let mut min = Point3::new(f64::MAX, f64::MAX, f64::MAX);
+    let mut max = Point3::new(f64::MIN, f64::MIN, f64::MIN);
+    for corner in corners(bbox) {
+        let corner = matrix.transform_point(corner);
+        min = Point3::new(
+            min.x.min(corner.x),
+            min.y.min(corner.y),
+            min.z.min(corner.z),
+        );
+        max = Point3::new(
+            max.x.max(corner.x),
+            max.y.max(corner.y),
+            max.z.max(corner.z),
+        );
+    }
+    Aabb::new(min, max)
+}
+
+/// Scaling, then rotation, then translation of an `Animated` hittable at one keyframe
+#[derive(Clone, Copy)]
+pub struct Placement {
+    pub translation: Vector3<f64>,
+    pub rotation: Quaternion<f64>,
+    /// Must not be zero in any axis
+    pub scale: Vector3<f64>,
+}
+
+impl Placement {
+    pub fn matrix(&self) -> Matrix4<f64> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    fn inverse(&self) -> Matrix4<f64> {
+        let scale = Vector3::new(1.0, 1.0, 1.0).div_element_wise(self.scale);
+        Matrix4::from_nonuniform_scale(scale.x, scale.y, scale.z)
+            * Matrix4::from(self.rotation.conjugate())
+            * Matrix4::from_translation(-self.translation)
+    }
+}
+
+impl Interpolate for Placement {
+    fn interpolate(self, other: Self, t: f64) -> Self {
+        Self {
+            translation: self.translation.interpolate(other.translation, t),
+            rotation: self.rotation.interpolate(other.rotation, t),
+            scale: self.scale.interpolate(other.scale, t),
+        }
+    }
+}
+
+/// Number of placements sampled between two keyframes to bound an `Animated` hittable
+const BOUNDING_STEPS: usize = 16;
+
+/// Places a hittable by keyframed placements over time, so anything can move, turn and grow
+/// during the exposure and be blurred by it. Rotations are interpolated by slerp, taking the
+/// shorter way, so consecutive keyframes must be less than half a turn apart.
+pub struct Animated<H: Hittable> {
+    hitable: H,
+    keyframes: Keyframes<Placement>,
+}
+
+impl<H: Hittable> Animated<H> {
+    pub fn new(hitable: H, keyframes: Keyframes<Placement>) -> Self {
+        Self { hitable, keyframes }
+    }
+
+    /// Light sampling does not know the time of the ray, so lights are sampled where they are
+    /// halfway between the first and the last keyframe. Away from there, they are mostly
+    /// found by bouncing rays.
+    fn light_placement(&self) -> Placement {
+        let (first, last) = self.keyframes.span();
+        self.keyframes.at(0.5 * (first + last))
+    }
+}
+
+impl<H: Hittable> Hittable for Animated<H> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let placement = self.keyframes.at(ray.time());
+        let inverse = placement.inverse();
+        hit_transformed(
+            &self.hitable,
+            ray,
+            t_min,
+            t_max,
+            &placement.matrix(),
+            &inverse,
+            &inverse.transpose(),
+        )
+    }
+
+    /// Union of the boxes at closely spaced times, each padded by the most the corners can
+    /// bulge out while turning to the next.
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        let bbox = self.hitable.bounding_box(time0, time1)?;
+        let radius = corners(&bbox)
+            .map(|corner| corner.to_vec().magnitude())
+            .fold(0.0, f64::max);
+        let times: Vec<f64> = iter::once(time0)
+            .chain(self.keyframes.times_between(time0, time1))
+            .chain(iter::once(time1))
+            .collect();
+        times
+            .windows(2)
+            .flat_map(|segment| {
+                let (start, end) = (segment[0], segment[1]);
+                let (first, last) = (self.keyframes.at(start), self.keyframes.at(end));
+                // Between keyframes, slerp turns at a constant rate
+                let angle = 2.0 * first.rotation.dot(last.rotation).abs().min(1.0).acos();
+                let scale = [first.scale, last.scale]
+                    .iter()
+                    .flat_map(|scale| [scale.x.abs(), scale.y.abs(), scale.z.abs()])
+                    .fold(0.0, f64::max);
+                let step = angle / BOUNDING_STEPS as f64;
+                let margin = radius * scale * (1.0 - (step / 2.0).cos());
+                let margin = Vector3::new(margin, margin, margin);
+                (0..=BOUNDING_STEPS).map(move |i| {
+                    let time = start + (end - start) * i as f64 / BOUNDING_STEPS as f64;
+                    let placed = transform_box(&bbox, &self.keyframes.at(time).matrix());
+                    Aabb::new(placed.min() - margin, placed.max() + margin)
+                })
+            })
+            .reduce(|a, b| Aabb::surrounding_box(&a, &b))
+    }
+
+    fn lights<'a>(&'a self, lights: &mut Vec<Emitter<'a>>) {
+        let placement = self.light_placement();
+        lights_transformed(
+            &self.hitable,
+            lights,
+            &placement.matrix(),
+            &placement.inverse(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        aarect::XZRect, color::Color, hitable_list::HitableList, material::DiffuseLight,
+        sphere::Sphere,
+    };
+    use cgmath::Rotation3;
+    use rand::{Rng, RngCore, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+    use raytracer::random_unit_vector;
+    use std::{f64::consts::PI, sync::Arc};
+
+    /// A rectangular and a spherical light, rotated, stretched and moved
+    fn lights() -> Transform<HitableList> {
+        let light = Arc::new(DiffuseLight::with_color(&Color::new(1.0, 1.0, 1.0)));
+        let mut list = HitableList::new();
+        list.push(XZRect {
+            material: light.clone(),
+            x0: -1.0,
+            x1: 1.0,
+            z0: -0.5,
+            z1: 0.5,
+            k: 0.0,
+        });
+        list.push(Sphere::new(Point3::new(0.0, 1.5, 0.0), 0.5, light));
+        Transform::with_matrix(
+            list,
+            Matrix4::from_translation(Vector3::new(0.5, 3.0, -1.0))
+                * Matrix4::from_axis_angle(Vector3::new(1.0, 0.0, 1.0).normalize(), Deg(40.0))
+                * Matrix4::from_nonuniform_scale(2.0, 0.5, 1.5),
+        )
+    }
+
+    /// Pdf of `random` choosing among `lights` with equal probability, like `Lights` does
+    fn pdf_value(lights: &[Emitter], origin: Point3<f64>, direction: Vector3<f64>) -> f64 {
+        lights
+            .iter()
+            .map(|light| light.pdf_value(origin, direction))
+            .sum::<f64>()
+            / lights.len() as f64
+    }
+
+    fn random(lights: &[Emitter], origin: Point3<f64>, rng: &mut dyn RngCore) -> Vector3<f64> {
+        lights[rng.gen_range(0..lights.len())].random(origin, rng)
+    }
+
+    #[test]
+    fn lights_are_forwarded() {
+        let transform = lights();
+        let mut lights = vec![];
+        transform.lights(&mut lights);
+        assert_eq!(lights.len(), 2);
+    }
+
+    #[test]
+    fn pdf_integrates_to_one() {
+        let transform = lights();
+        let mut lights = vec![];
+        transform.lights(&mut lights);
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let mut rng = ChaCha8Rng::seed_from_u64(4);
+        const SAMPLES: usize = 200_000;
+        let integral = (0..SAMPLES)
+            .map(|_| pdf_value(&lights, origin, random_unit_vector(&mut rng)))
+            .sum::<f64>()
+            * 4.0
+            * PI
+            / SAMPLES as f64;
+        assert!((integral - 1.0).abs() < 0.02, "integral {}", integral);
+    }
+
+    #[test]
+    fn animated_pdf_integrates_to_one() {
+        let keyframes = Keyframes::new(vec![
+            (
+                0.0,
+                Placement {
+                    translation: Vector3::new(0.0, -1.0, 0.0),
+                    rotation: Quaternion::from_angle_x(Deg(-10.0)),
+                    scale: Vector3::new(1.0, 1.0, 1.0),
+                },
+            ),
+            (
+                2.0,
+                Placement {
+                    translation: Vector3::new(0.5, -1.5, 0.5),
+                    rotation: Quaternion::from_angle_z(Deg(20.0)),
+                    scale: Vector3::new(1.5, 1.0, 0.5),
+                },
+            ),
+        ])
+        .unwrap();
+        let animated = Animated::new(lights(), keyframes);
+        let mut lights = vec![];
+        animated.lights(&mut lights);
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let mut rng = ChaCha8Rng::seed_from_u64(6);
+        const SAMPLES: usize = 200_000;
+        let integral = (0..SAMPLES)
+            .map(|_| pdf_value(&lights, origin, random_unit_vector(&mut rng)))
+            .sum::<f64>()
+            * 4.0
+            * PI
+            / SAMPLES as f64;
+        assert!((integral - 1.0).abs() < 0.02, "integral {}", integral);
+
+        // Sampled where the lights are halfway through the keyframes
+        for _ in 0..1000 {
+            let direction = random(&lights, origin, &mut rng);
+            let ray = Ray::new(origin, direction, 1.0);
+            assert!(animated.hit(&ray, 0.001, f64::MAX).is_some());
+        }
+    }
+
+    #[test]
+    fn random_hits_the_lights() {
+        let transform = lights();
+        let mut lights = vec![];
+        transform.lights(&mut lights);
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let mut rng = ChaCha8Rng::seed_from_u64(5);
+        for _ in 0..1000 {
+            let direction = random(&lights, origin, &mut rng);
+            let ray = Ray::new(origin, direction, 0.0);
+            assert!(transform.hit(&ray, 0.001, f64::MAX).is_some());
+            assert!(pdf_value(&lights, origin, direction) > 0.0);
+        }
+    }
+}
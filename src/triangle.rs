@@ -0,0 +1,329 @@
+use crate::{
+    aabb::Aabb,
+    bvh::{Bvh, BvhSplit},
+    hitable::*,
+    material::Material,
+    ray::Ray,
+};
+use cgmath::*;
+use rand::{Rng, RngCore};
+use std::{option::Option, sync::Arc};
+
+/// Vertex buffer shared by all triangles of a mesh. `normals` and `uvs` are either empty or
+/// hold one entry per position.
+pub struct Mesh {
+    pub positions: Vec<Point3<f64>>,
+    pub normals: Vec<Vector3<f64>>,
+    pub uvs: Vec<(f64, f64)>,
+    pub indices: Vec<[usize; 3]>,
+    pub material: Arc<dyn Material>,
+}
+
+impl Mesh {
+    pub fn triangles(self: &Arc<Self>) -> impl Iterator<Item = Triangle> + '_ {
+        (0..self.indices.len()).map(move |index| Triangle {
+            mesh: self.clone(),
+            index,
+        })
+    }
+}
+
+pub struct Triangle {
+    mesh: Arc<Mesh>,
+    index: usize,
+}
+
+impl Triangle {
+    fn vertices(&self) -> [Point3<f64>; 3] {
+        let [i0, i1, i2] = self.mesh.indices[self.index];
+        [
+            self.mesh.positions[i0],
+            self.mesh.positions[i1],
+            self.mesh.positions[i2],
+        ]
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        // Möller–Trumbore intersection
+        const EPSILON: f64 = 1e-12;
+        let [p0, p1, p2] = self.vertices();
+        let edge1 = p1 - p0;
+        let edge2 = p2 - p0;
+        let pvec = ray.direction().cross(edge2);
+        let det = edge1.dot(pvec);
+        if det.abs() < EPSILON {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+
+        let tvec = ray.origin() - p0;
+        let b1 = tvec.dot(pvec) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+        let qvec = tvec.cross(edge1);
+        let b2 = ray.direction().dot(qvec) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+        let t = edge2.dot(qvec) * inv_det;
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        let b0 = 1.0 - b1 - b2;
+        let [i0, i1, i2] = self.mesh.indices[self.index];
+        // Which side is hit is decided by the flat triangle, facing the side its vertex
+        // normals agree on. The interpolated normal may tilt past the surface near
+        // silhouettes, so it is turned to the same side and used for shading only.
+        let mut outward_normal = edge1.cross(edge2).normalize();
+        let mut shading_normal = outward_normal;
+        if !self.mesh.normals.is_empty() {
+            let normals = &self.mesh.normals;
+            if outward_normal.dot(normals[i0] + normals[i1] + normals[i2]) < 0.0 {
+                outward_normal = -outward_normal;
+            }
+            shading_normal = (b0 * normals[i0] + b1 * normals[i1] + b2 * normals[i2]).normalize();
+            if shading_normal.dot(outward_normal) < 0.0 {
+                shading_normal = -shading_normal;
+            }
+        }
+        let (u, v) = if self.mesh.uvs.is_empty() {
+            (b1, b2)
+        } else {
+            let uvs = &self.mesh.uvs;
+            (
+                b0 * uvs[i0].0 + b1 * uvs[i1].0 + b2 * uvs[i2].0,
+                b0 * uvs[i0].1 + b1 * uvs[i1].1 + b2 * uvs[i2].1,
+            )
+        };
+
+        let mut record = HitRecord {
+            p: ray.at(t),
+            normal: outward_normal,
+            material: self.mesh.material.as_ref(),
+            t,
+            u,
+            v,
+            front_face: false,
+        };
+        record.set_face_normal(ray, outward_normal);
+        record.normal = if record.front_face {
+            shading_normal
+        } else {
+            -shading_normal
+        };
+        Some(record)
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64) -> Option<Aabb> {
+        // Axis aligned triangles have no extent in one dimension, so pad all dimensions a
+        // small amount.
+        let [p0, p1, p2] = self.vertices();
+        let padding = Vector3::new(0.0001, 0.0001, 0.0001);
+        let minimum = Point3::new(
+            p0.x.min(p1.x).min(p2.x),
+            p0.y.min(p1.y).min(p2.y),
+            p0.z.min(p1.z).min(p2.z),
+        );
+        let maximum = Point3::new(
+            p0.x.max(p1.x).max(p2.x),
+            p0.y.max(p1.y).max(p2.y),
+            p0.z.max(p1.z).max(p2.z),
+        );
+        Some(Aabb::new(minimum - padding, maximum + padding))
+    }
+
+    fn pdf_value(&self, origin: Point3<f64>, direction: Vector3<f64>) -> f64 {
+        match self.hit(&Ray::new(origin, direction, 0.0), 0.001, f64::MAX) {
+            Some(record) => {
+                // `record.normal` may be interpolated from the vertex normals, but `random`
+                // samples the flat triangle, so project its area along the geometric normal.
+                let [p0, p1, p2] = self.vertices();
+                let normal = (p1 - p0).cross(p2 - p0);
+                let projected_area = 0.5 * direction.dot(normal).abs() / direction.magnitude();
+                if projected_area < 1e-12 {
+                    return 0.0;
+                }
+                let distance_squared = record.t * record.t * direction.magnitude2();
+                distance_squared / projected_area
+            }
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: Point3<f64>, rng: &mut dyn RngCore) -> Vector3<f64> {
+        // Uniformly distributed barycentric coordinates
+        let [p0, p1, p2] = self.vertices();
+        let r1: f64 = rng.gen_range(0.0..1.0);
+        let r2: f64 = rng.gen_range(0.0..1.0);
+        let s = r1.sqrt();
+        let random_point = p0 + s * (1.0 - r2) * (p1 - p0) + s * r2 * (p2 - p0);
+        random_point - origin
+    }
+
+    fn lights<'a>(&'a self, lights: &mut Vec<Emitter<'a>>) {
+        if self.mesh.material.is_emissive() {
+            lights.push(Emitter::new(self));
+        }
+    }
+}
+
+/// All triangles of a mesh, held in a `Bvh`.
+pub struct TriangleMesh {
+    triangles: Bvh,
+}
+
+impl TriangleMesh {
+    pub fn new(mesh: Mesh, time0: f64, time1: f64) -> Self {
+        let mesh = Arc::new(mesh);
+        let triangles = mesh
+            .triangles()
+            .map(|triangle| Box::new(triangle) as Box<dyn Hittable>)
+            .collect();
+        Self {
+            triangles: Bvh::with_split(triangles, time0, time1, BvhSplit::default()),
+        }
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.triangles.hit(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64) -> Option<Aabb> {
+        self.triangles.bounding_box(time0, time1)
+    }
+
+    fn lights<'a>(&'a self, lights: &mut Vec<Emitter<'a>>) {
+        self.triangles.lights(lights)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{color::Color, material::Lambertian, texture::SolidColor};
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+    use raytracer::random_unit_vector;
+    use std::f64::consts::PI;
+
+    /// A tilted triangle whose vertex normals differ from its geometric normal
+    fn triangle() -> Triangle {
+        let mesh = Mesh {
+            positions: vec![
+                Point3::new(-1.0, -0.5, -2.0),
+                Point3::new(1.5, -0.5, -3.0),
+                Point3::new(0.0, 1.0, -2.5),
+            ],
+            normals: vec![
+                Vector3::new(1.0, 0.0, 1.0).normalize(),
+                Vector3::new(0.0, 1.0, 1.0).normalize(),
+                Vector3::new(-1.0, -1.0, 1.0).normalize(),
+            ],
+            uvs: vec![],
+            indices: vec![[0, 1, 2]],
+            material: Arc::new(Lambertian::new(Arc::new(SolidColor::new(&Color::new(
+                0.5, 0.5, 0.5,
+            ))))),
+        };
+        Triangle {
+            mesh: Arc::new(mesh),
+            index: 0,
+        }
+    }
+
+    #[test]
+    fn front_face_from_geometry() {
+        // Flat in the xy plane, facing +z, with vertex normals tilted far towards +x
+        let tilted = Vector3::new(1.0, 0.0, 0.2).normalize();
+        let mesh = Mesh {
+            positions: vec![
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(1.0, 0.0, 0.0),
+                Point3::new(0.0, 1.0, 0.0),
+            ],
+            normals: vec![tilted; 3],
+            uvs: vec![],
+            indices: vec![[0, 1, 2]],
+            material: Arc::new(Lambertian::new(Arc::new(SolidColor::new(&Color::new(
+                0.5, 0.5, 0.5,
+            ))))),
+        };
+        let triangle = Triangle {
+            mesh: Arc::new(mesh),
+            index: 0,
+        };
+
+        // From above at a grazing angle, along the tilted normal
+        let ray = Ray::new(
+            Point3::new(-0.75, 0.25, 0.1),
+            Vector3::new(1.0, 0.0, -0.1),
+            0.0,
+        );
+        let record = triangle.hit(&ray, 0.001, f64::MAX).unwrap();
+        assert!(record.front_face);
+        assert!((record.normal - tilted).magnitude() < 1e-12);
+
+        // From below
+        let ray = Ray::new(
+            Point3::new(0.25, 0.25, -1.0),
+            Vector3::new(0.0, 0.0, 1.0),
+            0.0,
+        );
+        let record = triangle.hit(&ray, 0.001, f64::MAX).unwrap();
+        assert!(!record.front_face);
+        assert!((record.normal + tilted).magnitude() < 1e-12);
+    }
+
+    #[test]
+    fn pdf_integrates_to_one() {
+        let triangle = triangle();
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        const SAMPLES: usize = 200_000;
+        let integral = (0..SAMPLES)
+            .map(|_| triangle.pdf_value(origin, random_unit_vector(&mut rng)))
+            .sum::<f64>()
+            * 4.0
+            * PI
+            / SAMPLES as f64;
+        assert!((integral - 1.0).abs() < 0.02, "integral {}", integral);
+    }
+
+    #[test]
+    fn pdf_matches_random() {
+        let triangle = triangle();
+        let origin = Point3::new(0.0, 0.0, 0.0);
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+
+        // Solid angle of the triangle after Van Oosterom and Strackee
+        let [a, b, c] = triangle.vertices().map(|p| p - origin);
+        let (la, lb, lc) = (a.magnitude(), b.magnitude(), c.magnitude());
+        let solid_angle = 2.0
+            * a.dot(b.cross(c))
+                .abs()
+                .atan2(la * lb * lc + a.dot(b) * lc + a.dot(c) * lb + b.dot(c) * la);
+
+        // The mean inverse pdf of the sampled directions estimates the solid angle.
+        const SAMPLES: usize = 100_000;
+        let estimate = (0..SAMPLES)
+            .map(|_| {
+                let pdf = triangle.pdf_value(origin, triangle.random(origin, &mut rng));
+                assert!(pdf > 0.0);
+                1.0 / pdf
+            })
+            .sum::<f64>()
+            / SAMPLES as f64;
+        assert!(
+            (estimate / solid_angle - 1.0).abs() < 0.01,
+            "estimate {}, solid angle {}",
+            estimate,
+            solid_angle
+        );
+    }
+}
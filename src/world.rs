@@ -4,15 +4,18 @@ use crate::{
     color::{self, *},
     constant_medium::ConstantMedium,
     cuboid::Cuboid,
+    hitable::Hittable,
     hitable_list::HitableList,
+    instance::Instance,
     material::*,
-    rotate::RotateY,
     sphere::*,
     texture::*,
-    translate::Translate,
+    transform::Transform,
+    triangle::{Mesh, TriangleMesh},
 };
-use cgmath::{InnerSpace, Point3, Vector3};
+use cgmath::{Deg, InnerSpace, Matrix4, Point3, Vector3};
 use rand::{Rng, RngCore};
+use std::{f64::consts::PI, sync::Arc};
 
 #[allow(dead_code)]
 pub fn empty(_rng: &mut dyn RngCore) -> HitableList {
@@ -23,11 +26,11 @@ pub fn random_scene(rng: &mut dyn RngCore) -> HitableList {
     // TODO: Create Vec<Box<dyn Hittable>> first and pass this to world
     let mut world = HitableList::new();
 
-    let checker_texture = Box::new(CheckerTexture::new(
-        Box::new(SolidColor::new(&Color::new(0.2, 0.3, 0.1))),
-        Box::new(SolidColor::new(&Color::new(0.9, 0.9, 0.9))),
+    let checker_texture = Arc::new(CheckerTexture::new(
+        Arc::new(SolidColor::new(&Color::new(0.2, 0.3, 0.1))),
+        Arc::new(SolidColor::new(&Color::new(0.9, 0.9, 0.9))),
     ));
-    let ground_material = Box::new(Lambertian::new(checker_texture));
+    let ground_material = Arc::new(Lambertian::new(checker_texture));
     world.push(Sphere::new(
         Point3 {
             x: 0.0,
@@ -58,7 +61,7 @@ pub fn random_scene(rng: &mut dyn RngCore) -> HitableList {
             {
                 if choose_mat < 0.8 {
                     // diffuse
-                    let albedo = Box::new(SolidColor::new(&random_color(rng)));
+                    let albedo = Arc::new(SolidColor::new(&random_color(rng)));
                     let center2 = center
                         + Vector3 {
                             x: 0.0,
@@ -71,7 +74,7 @@ pub fn random_scene(rng: &mut dyn RngCore) -> HitableList {
                         0.0,
                         1.0,
                         0.2,
-                        Box::new(Lambertian::new(albedo)),
+                        Arc::new(Lambertian::new(albedo)),
                     ));
                 } else if choose_mat < 0.95 {
                     // metal
@@ -80,17 +83,17 @@ pub fn random_scene(rng: &mut dyn RngCore) -> HitableList {
                     world.push(Sphere::new(
                         center,
                         0.2,
-                        Box::new(Metal::new(&albedo, fuzz)),
+                        Arc::new(Metal::new(&albedo, fuzz)),
                     ));
                 } else {
                     // glass
-                    world.push(Sphere::new(center, 0.2, Box::new(Dielectric::new(1.5))));
+                    world.push(Sphere::new(center, 0.2, Arc::new(Dielectric::new(1.5))));
                 };
             }
         }
     }
 
-    let material1 = Box::new(Dielectric::new(1.5));
+    let material1 = Arc::new(Dielectric::new(1.5));
     world.push(Sphere::new(
         Point3 {
             x: 0.0,
@@ -101,8 +104,8 @@ pub fn random_scene(rng: &mut dyn RngCore) -> HitableList {
         material1,
     ));
 
-    let color2 = Box::new(SolidColor::new(&Color::new(0.4, 0.2, 0.1)));
-    let material2 = Box::new(Lambertian::new(color2));
+    let color2 = Arc::new(SolidColor::new(&Color::new(0.4, 0.2, 0.1)));
+    let material2 = Arc::new(Lambertian::new(color2));
     world.push(Sphere::new(
         Point3 {
             x: -4.0,
@@ -113,7 +116,7 @@ pub fn random_scene(rng: &mut dyn RngCore) -> HitableList {
         material2,
     ));
 
-    let material3 = Box::new(Metal::new(&Color::new(0.7, 0.6, 0.5), 0.0));
+    let material3 = Arc::new(Metal::new(&Color::new(0.7, 0.6, 0.5), 0.0));
     world.push(Sphere::new(
         Point3 {
             x: 4.0,
@@ -130,9 +133,9 @@ pub fn random_scene(rng: &mut dyn RngCore) -> HitableList {
 pub fn two_spheres(_rng: &mut dyn RngCore) -> HitableList {
     let mut world = HitableList::new();
 
-    let checker = Box::new(CheckerTexture::new(
-        Box::new(SolidColor::new(&Color::new(0.2, 0.3, 0.1))),
-        Box::new(SolidColor::new(&Color::new(0.9, 0.9, 0.9))),
+    let checker = Arc::new(CheckerTexture::new(
+        Arc::new(SolidColor::new(&Color::new(0.2, 0.3, 0.1))),
+        Arc::new(SolidColor::new(&Color::new(0.9, 0.9, 0.9))),
     ));
     world.push(Sphere::new(
         Point3 {
@@ -141,7 +144,7 @@ pub fn two_spheres(_rng: &mut dyn RngCore) -> HitableList {
             z: 0.0,
         },
         10.0,
-        Box::new(Lambertian::new(checker.clone())),
+        Arc::new(Lambertian::new(checker.clone())),
     ));
     world.push(Sphere::new(
         Point3 {
@@ -150,7 +153,7 @@ pub fn two_spheres(_rng: &mut dyn RngCore) -> HitableList {
             z: 0.0,
         },
         10.0,
-        Box::new(Lambertian::new(checker)),
+        Arc::new(Lambertian::new(checker)),
     ));
 
     world
@@ -159,7 +162,7 @@ pub fn two_spheres(_rng: &mut dyn RngCore) -> HitableList {
 pub fn two_perlin_spheres(rng: &mut dyn RngCore) -> HitableList {
     let mut world = HitableList::new();
 
-    let noise = Box::new(NoiseTexture::new(4.0, rng));
+    let noise = Arc::new(NoiseTexture::new(4.0, rng));
     world.push(Sphere::new(
         Point3 {
             x: 0.0,
@@ -167,7 +170,7 @@ pub fn two_perlin_spheres(rng: &mut dyn RngCore) -> HitableList {
             z: 0.0,
         },
         1000.0,
-        Box::new(Lambertian::new(noise.clone())),
+        Arc::new(Lambertian::new(noise.clone())),
     ));
     world.push(Sphere::new(
         Point3 {
@@ -176,7 +179,7 @@ pub fn two_perlin_spheres(rng: &mut dyn RngCore) -> HitableList {
             z: 0.0,
         },
         2.0,
-        Box::new(Lambertian::new(noise)),
+        Arc::new(Lambertian::new(noise)),
     ));
 
     world
@@ -189,7 +192,7 @@ pub fn earth(_rng: &mut dyn RngCore) -> HitableList {
         .to_rgb8();
     let (width, height) = image.dimensions();
     let data = image.into_raw();
-    let earth_texture = Box::new(ImageTexture::new(data, width as usize, height as usize));
+    let earth_texture = Arc::new(ImageTexture::new(data, width as usize, height as usize));
     world.push(Sphere::new(
         Point3 {
             x: 0.0,
@@ -197,7 +200,7 @@ pub fn earth(_rng: &mut dyn RngCore) -> HitableList {
             z: 0.0,
         },
         2.0,
-        Box::new(Lambertian::new(earth_texture)),
+        Arc::new(Lambertian::new(earth_texture)),
     ));
 
     world
@@ -206,7 +209,7 @@ pub fn earth(_rng: &mut dyn RngCore) -> HitableList {
 pub fn simple_light(rng: &mut dyn RngCore) -> HitableList {
     let mut world = HitableList::new();
 
-    let noise = Box::new(NoiseTexture::new(4.0, rng));
+    let noise = Arc::new(NoiseTexture::new(4.0, rng));
     world.push(Sphere::new(
         Point3 {
             x: 0.0,
@@ -214,7 +217,7 @@ pub fn simple_light(rng: &mut dyn RngCore) -> HitableList {
             z: 0.0,
         },
         1000.0,
-        Box::new(Lambertian::new(noise.clone())),
+        Arc::new(Lambertian::new(noise.clone())),
     ));
     world.push(Sphere::new(
         Point3 {
@@ -223,7 +226,7 @@ pub fn simple_light(rng: &mut dyn RngCore) -> HitableList {
             z: 0.0,
         },
         2.0,
-        Box::new(Lambertian::new(noise)),
+        Arc::new(Lambertian::new(noise)),
     ));
 
     world.push(XYRect {
@@ -241,10 +244,17 @@ pub fn simple_light(rng: &mut dyn RngCore) -> HitableList {
 pub fn cornell_box(_rng: &mut dyn RngCore) -> HitableList {
     let mut world = HitableList::new();
 
-    let red = Lambertian::new(Box::new(SolidColor::new(&Color::new(0.65, 0.05, 0.05))));
-    let white = Lambertian::new(Box::new(SolidColor::new(&Color::new(0.73, 0.73, 0.73))));
-    let green = Lambertian::new(Box::new(SolidColor::new(&Color::new(0.12, 0.45, 0.15))));
-    let light = DiffuseLight::with_color(&Color::new(15.0, 15.0, 15.0));
+    let red: Arc<dyn Material> = Arc::new(Lambertian::new(Arc::new(SolidColor::new(&Color::new(
+        0.65, 0.05, 0.05,
+    )))));
+    let white: Arc<dyn Material> = Arc::new(Lambertian::new(Arc::new(SolidColor::new(
+        &Color::new(0.73, 0.73, 0.73),
+    ))));
+    let green: Arc<dyn Material> = Arc::new(Lambertian::new(Arc::new(SolidColor::new(
+        &Color::new(0.12, 0.45, 0.15),
+    ))));
+    let light: Arc<dyn Material> =
+        Arc::new(DiffuseLight::with_color(&Color::new(15.0, 15.0, 15.0)));
 
     world.push(YZRect {
         material: green,
@@ -308,15 +318,11 @@ pub fn cornell_box(_rng: &mut dyn RngCore) -> HitableList {
         },
         white.clone(),
     );
-    let box1 = RotateY::new(box1, 15.0);
-    let box1 = Translate::new(
-        Vector3 {
-            x: 265.0,
-            y: 0.0,
-            z: 295.0,
-        },
-        box1,
-    );
+    let box1 = Transform::new(box1).rotate_y(15.0).translate(Vector3 {
+        x: 265.0,
+        y: 0.0,
+        z: 295.0,
+    });
     world.push(box1);
     let box2 = Cuboid::new(
         Point3 {
@@ -331,15 +337,11 @@ pub fn cornell_box(_rng: &mut dyn RngCore) -> HitableList {
         },
         white,
     );
-    let box2 = RotateY::new(box2, -18.0);
-    let box2 = Translate::new(
-        Vector3 {
-            x: 130.0,
-            y: 0.0,
-            z: 65.0,
-        },
-        box2,
-    );
+    let box2 = Transform::new(box2).rotate_y(-18.0).translate(Vector3 {
+        x: 130.0,
+        y: 0.0,
+        z: 65.0,
+    });
     world.push(box2);
 
     world
@@ -348,10 +350,16 @@ pub fn cornell_box(_rng: &mut dyn RngCore) -> HitableList {
 pub fn cornell_smoke(_rng: &mut dyn RngCore) -> HitableList {
     let mut world = HitableList::new();
 
-    let red = Lambertian::new(Box::new(SolidColor::new(&Color::new(0.65, 0.05, 0.05))));
-    let white = Lambertian::new(Box::new(SolidColor::new(&Color::new(0.73, 0.73, 0.73))));
-    let green = Lambertian::new(Box::new(SolidColor::new(&Color::new(0.12, 0.45, 0.15))));
-    let light = DiffuseLight::with_color(&Color::new(7.0, 7.0, 7.0));
+    let red: Arc<dyn Material> = Arc::new(Lambertian::new(Arc::new(SolidColor::new(&Color::new(
+        0.65, 0.05, 0.05,
+    )))));
+    let white: Arc<dyn Material> = Arc::new(Lambertian::new(Arc::new(SolidColor::new(
+        &Color::new(0.73, 0.73, 0.73),
+    ))));
+    let green: Arc<dyn Material> = Arc::new(Lambertian::new(Arc::new(SolidColor::new(
+        &Color::new(0.12, 0.45, 0.15),
+    ))));
+    let light: Arc<dyn Material> = Arc::new(DiffuseLight::with_color(&Color::new(7.0, 7.0, 7.0)));
 
     world.push(YZRect {
         material: green,
@@ -415,19 +423,15 @@ pub fn cornell_smoke(_rng: &mut dyn RngCore) -> HitableList {
         },
         white.clone(),
     );
-    let box1 = RotateY::new(box1, 15.0);
-    let box1 = Translate::new(
-        Vector3 {
-            x: 265.0,
-            y: 0.0,
-            z: 295.0,
-        },
-        box1,
-    );
+    let box1 = Transform::new(box1).rotate_y(15.0).translate(Vector3 {
+        x: 265.0,
+        y: 0.0,
+        z: 295.0,
+    });
     world.push(ConstantMedium::new(
         box1,
         0.01,
-        SolidColor::new(&color::black()),
+        Arc::new(SolidColor::new(&color::black())),
     ));
     let box2 = Cuboid::new(
         Point3 {
@@ -442,19 +446,15 @@ pub fn cornell_smoke(_rng: &mut dyn RngCore) -> HitableList {
         },
         white,
     );
-    let box2 = RotateY::new(box2, -18.0);
-    let box2 = Translate::new(
-        Vector3 {
-            x: 130.0,
-            y: 0.0,
-            z: 65.0,
-        },
-        box2,
-    );
+    let box2 = Transform::new(box2).rotate_y(-18.0).translate(Vector3 {
+        x: 130.0,
+        y: 0.0,
+        z: 65.0,
+    });
     world.push(ConstantMedium::new(
         box2,
         0.01,
-        SolidColor::new(&color::white()),
+        Arc::new(SolidColor::new(&color::white())),
     ));
 
     world
@@ -463,7 +463,9 @@ pub fn cornell_smoke(_rng: &mut dyn RngCore) -> HitableList {
 pub fn final_scene(rng: &mut dyn RngCore) -> HitableList {
     let mut boxes = HitableList::new();
 
-    let ground = Lambertian::new(Box::new(SolidColor::new(&Color::new(0.48, 0.83, 0.53))));
+    let ground: Arc<dyn Material> = Arc::new(Lambertian::new(Arc::new(SolidColor::new(
+        &Color::new(0.48, 0.83, 0.53),
+    ))));
     const BOXES_PER_SIDE: usize = 20;
 
     for i in 0..BOXES_PER_SIDE {
@@ -495,7 +497,7 @@ pub fn final_scene(rng: &mut dyn RngCore) -> HitableList {
 
     world.push(Bvh::new(boxes.objects, 0.0, 1.0));
 
-    let light = DiffuseLight::with_color(&Color::new(7.0, 7.0, 7.0));
+    let light: Arc<dyn Material> = Arc::new(DiffuseLight::with_color(&Color::new(7.0, 7.0, 7.0)));
     world.push(XZRect {
         material: light,
         x0: 123.0,
@@ -516,7 +518,7 @@ pub fn final_scene(rng: &mut dyn RngCore) -> HitableList {
             y: 0.0,
             z: 0.0,
         };
-    let moving_sphere_material = Box::new(Lambertian::new(Box::new(SolidColor::new(&Color::new(
+    let moving_sphere_material = Arc::new(Lambertian::new(Arc::new(SolidColor::new(&Color::new(
         0.7, 0.3, 0.1,
     )))));
     world.push(MovingSphere::new(
@@ -535,7 +537,7 @@ pub fn final_scene(rng: &mut dyn RngCore) -> HitableList {
             z: 45.0,
         },
         50.0,
-        Box::new(Dielectric::new(1.5)),
+        Arc::new(Dielectric::new(1.5)),
     ));
     world.push(Sphere::new(
         Point3 {
@@ -544,33 +546,23 @@ pub fn final_scene(rng: &mut dyn RngCore) -> HitableList {
             z: 145.0,
         },
         50.0,
-        Box::new(Metal::new(&Color::new(0.8, 0.8, 0.9), 1.0)),
+        Arc::new(Metal::new(&Color::new(0.8, 0.8, 0.9), 1.0)),
     ));
 
-    let boundary = Sphere::new(
-        Point3 {
-            x: 360.0,
-            y: 150.0,
-            z: 145.0,
-        },
-        70.0,
-        Box::new(Dielectric::new(1.5)),
-    );
-    world.push(boundary);
-    //TODO: Make Sphere clonable
-    let boundary = Sphere::new(
+    let boundary = Arc::new(Sphere::new(
         Point3 {
             x: 360.0,
             y: 150.0,
             z: 145.0,
         },
         70.0,
-        Box::new(Dielectric::new(1.5)),
-    );
+        Arc::new(Dielectric::new(1.5)),
+    ));
+    world.push(boundary.clone());
     world.push(ConstantMedium::new(
         boundary,
         0.2,
-        SolidColor::new(&Color::new(0.2, 0.4, 0.9)),
+        Arc::new(SolidColor::new(&Color::new(0.2, 0.4, 0.9))),
     ));
     let boundary = Sphere::new(
         Point3 {
@@ -579,12 +571,12 @@ pub fn final_scene(rng: &mut dyn RngCore) -> HitableList {
             z: 0.0,
         },
         5000.0,
-        Box::new(Dielectric::new(1.5)),
+        Arc::new(Dielectric::new(1.5)),
     );
     world.push(ConstantMedium::new(
         boundary,
         0.0001,
-        SolidColor::new(&color::white()),
+        Arc::new(SolidColor::new(&color::white())),
     ));
 
     let image = image::open("earthmap.png")
@@ -592,7 +584,7 @@ pub fn final_scene(rng: &mut dyn RngCore) -> HitableList {
         .to_rgb8();
     let (width, height) = image.dimensions();
     let data = image.into_raw();
-    let earth_texture = Box::new(ImageTexture::new(data, width as usize, height as usize));
+    let earth_texture = Arc::new(ImageTexture::new(data, width as usize, height as usize));
     world.push(Sphere::new(
         Point3 {
             x: 400.0,
@@ -600,9 +592,9 @@ pub fn final_scene(rng: &mut dyn RngCore) -> HitableList {
             z: 400.0,
         },
         100.0,
-        Box::new(Lambertian::new(earth_texture)),
+        Arc::new(Lambertian::new(earth_texture)),
     ));
-    let pertext = Box::new(NoiseTexture::new(0.1, rng));
+    let pertext = Arc::new(NoiseTexture::new(0.1, rng));
     world.push(Sphere::new(
         Point3 {
             x: 220.0,
@@ -610,11 +602,13 @@ pub fn final_scene(rng: &mut dyn RngCore) -> HitableList {
             z: 300.0,
         },
         80.0,
-        Box::new(Lambertian::new(pertext)),
+        Arc::new(Lambertian::new(pertext)),
     ));
 
     let mut boxes = HitableList::new();
-    let white = Lambertian::new(Box::new(SolidColor::new(&Color::new(0.73, 0.73, 0.73))));
+    let white: Arc<dyn Material> = Arc::new(Lambertian::new(Arc::new(SolidColor::new(
+        &Color::new(0.73, 0.73, 0.73),
+    ))));
     const NS: usize = 1000;
     for _ in 0..NS {
         boxes.push(Sphere::new(
@@ -624,18 +618,94 @@ pub fn final_scene(rng: &mut dyn RngCore) -> HitableList {
                 z: rng.gen_range(0.0..165.0),
             },
             10.0,
-            Box::new(white.clone()),
+            white.clone(),
         ));
     }
 
-    world.push(Translate::new(
-        Vector3 {
-            x: -100.0,
-            y: 270.0,
-            z: 395.0,
+    world.push(Transform::new(boxes).rotate_y(15.0).translate(Vector3 {
+        x: -100.0,
+        y: 270.0,
+        z: 395.0,
+    }));
+
+    world
+}
+
+/// Cone around the y axis with its apex at `height` and an open base of `radius` at 0.
+fn cone(radius: f64, height: f64, material: Arc<dyn Material>) -> Mesh {
+    const SEGMENTS: usize = 16;
+    let mut positions = vec![Point3::new(0.0, height, 0.0)];
+    positions.extend((0..SEGMENTS).map(|i| {
+        let phi = 2.0 * PI * i as f64 / SEGMENTS as f64;
+        Point3::new(radius * phi.cos(), 0.0, radius * phi.sin())
+    }));
+    let indices = (0..SEGMENTS)
+        .map(|i| [0, (i + 1) % SEGMENTS + 1, i + 1])
+        .collect();
+    Mesh {
+        positions,
+        normals: vec![],
+        uvs: vec![],
+        indices,
+        material,
+    }
+}
+
+/// Ten thousand trees instancing the same two geometries, each tree randomly rotated,
+/// scaled and painted in its own shade of green.
+pub fn forest(rng: &mut dyn RngCore) -> HitableList {
+    let mut world = HitableList::new();
+
+    let ground: Arc<dyn Material> = Arc::new(Lambertian::new(Arc::new(SolidColor::new(
+        &Color::new(0.35, 0.3, 0.2),
+    ))));
+    world.push(Sphere::new(
+        Point3 {
+            x: 0.0,
+            y: -1000.0,
+            z: 0.0,
         },
-        RotateY::new(boxes, 15.0),
+        1000.0,
+        ground,
+    ));
+
+    let bark: Arc<dyn Material> = Arc::new(Lambertian::new(Arc::new(SolidColor::new(
+        &Color::new(0.3, 0.2, 0.1),
+    ))));
+    let trunk: Arc<dyn Hittable> = Arc::new(Cuboid::new(
+        Point3::new(-0.08, 0.0, -0.08),
+        Point3::new(0.08, 0.4, 0.08),
+        bark,
     ));
+    let leaves: Arc<dyn Material> = Arc::new(Lambertian::new(Arc::new(SolidColor::new(
+        &Color::new(0.1, 0.4, 0.1),
+    ))));
+    let crown: Arc<dyn Hittable> = Arc::new(TriangleMesh::new(cone(0.45, 1.4, leaves), 0.0, 1.0));
+
+    const TREES_PER_ROW: usize = 100;
+    const SPACING: f64 = 1.0;
+    for i in 0..TREES_PER_ROW {
+        for j in 0..TREES_PER_ROW {
+            let x = (i as f64 - TREES_PER_ROW as f64 / 2.0 + rng.gen_range(0.0..1.0)) * SPACING;
+            let z = (j as f64 - TREES_PER_ROW as f64 / 2.0 + rng.gen_range(0.0..1.0)) * SPACING;
+            let matrix = Matrix4::from_translation(Vector3::new(x, 0.0, z))
+                * Matrix4::from_angle_y(Deg(rng.gen_range(0.0..360.0)))
+                * Matrix4::from_scale(rng.gen_range(0.6..1.4));
+            let green = Color::new(
+                rng.gen_range(0.05..0.2),
+                rng.gen_range(0.3..0.6),
+                rng.gen_range(0.05..0.2),
+            );
+            world.push(Instance::new(trunk.clone(), matrix));
+            world.push(
+                Instance::new(
+                    crown.clone(),
+                    matrix * Matrix4::from_translation(Vector3::new(0.0, 0.4, 0.0)),
+                )
+                .with_material(Arc::new(Lambertian::new(Arc::new(SolidColor::new(&green))))),
+            );
+        }
+    }
 
     world
 }
@@ -0,0 +1,180 @@
+//! Renders each built-in world at low resolution with a fixed seed and compares the result
+//! against the reference images in `tests/references`.
+//!
+//! Images are compared by their relative mean squared error after averaging blocks of pixels,
+//! so that a change of the random number streams (e.g. a material drawing one more number)
+//! passes while a change of the rendered radiance fails. On failure the rendered image and a
+//! difference image are written to the `regression` directory in cargo's temporary target
+//! directory. Run with `UPDATE_REFERENCES=1` to replace the references after an intended
+//! change.
+
+use image::{DynamicImage, Rgb32FImage, RgbImage};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+const WIDTH: u32 = 64;
+const HEIGHT: u32 = 48;
+const SEED: u64 = 1;
+
+/// Edge length of the pixel blocks averaged before comparison
+const BLOCK_SIZE: u32 = 8;
+
+/// Maximum relative mean squared error between block averages
+const THRESHOLD: f64 = 0.002;
+
+fn reference_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("references")
+        .join(format!("{}.exr", name))
+}
+
+fn output_dir() -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("regression");
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn render(world: usize, samples_per_pixel: usize, output: &Path) -> Rgb32FImage {
+    let status = Command::new(env!("CARGO_BIN_EXE_raytracer"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(["--world", &world.to_string()])
+        .args(["--image-width", &WIDTH.to_string()])
+        .args(["--image-height", &HEIGHT.to_string()])
+        .args(["--samples-per-pixel", &samples_per_pixel.to_string()])
+        .args(["--seed", &SEED.to_string()])
+        .args(["--threads", "1"])
+        .arg("--output")
+        .arg(output)
+        .output()
+        .expect("failed to run raytracer");
+    assert!(
+        status.status.success(),
+        "raytracer failed: {}",
+        String::from_utf8_lossy(&status.stderr)
+    );
+    image::open(output).unwrap().into_rgb32f()
+}
+
+/// Average over blocks of `BLOCK_SIZE` x `BLOCK_SIZE` pixels.
+fn block_average(image: &Rgb32FImage) -> Vec<[f64; 3]> {
+    let (width, height) = (image.width() / BLOCK_SIZE, image.height() / BLOCK_SIZE);
+    let scale = 1.0 / (BLOCK_SIZE * BLOCK_SIZE) as f64;
+    let mut blocks = vec![[0.0; 3]; (width * height) as usize];
+    for (x, y, pixel) in image.enumerate_pixels() {
+        let (bx, by) = (x / BLOCK_SIZE, y / BLOCK_SIZE);
+        if bx < width && by < height {
+            let block = &mut blocks[(by * width + bx) as usize];
+            for (b, p) in block.iter_mut().zip(pixel.0) {
+                *b += p as f64 * scale;
+            }
+        }
+    }
+    blocks
+}
+
+/// Mean of the squared error relative to the squared reference value. The small offset
+/// keeps black regions from dominating.
+fn relative_mse(image: &Rgb32FImage, reference: &Rgb32FImage) -> f64 {
+    let image = block_average(image);
+    let reference = block_average(reference);
+    let sum: f64 = image
+        .iter()
+        .zip(&reference)
+        .flat_map(|(i, r)| i.iter().zip(r))
+        .map(|(i, r)| (i - r).powi(2) / (r * r + 0.01))
+        .sum();
+    sum / (3 * reference.len()) as f64
+}
+
+/// Absolute per-pixel difference, brightened so small errors stay visible.
+fn difference(image: &Rgb32FImage, reference: &Rgb32FImage) -> RgbImage {
+    RgbImage::from_fn(image.width(), image.height(), |x, y| {
+        let (i, r) = (image.get_pixel(x, y), reference.get_pixel(x, y));
+        let channel = |c: usize| (255.0 * (4.0 * (i[c] - r[c]).abs()).min(1.0)) as u8;
+        image::Rgb([channel(0), channel(1), channel(2)])
+    })
+}
+
+/// Render `world` with `samples_per_pixel`, chosen high enough for the noise to stay well
+/// below `THRESHOLD`, and compare it against the reference called `name`.
+fn check(name: &str, world: usize, samples_per_pixel: usize) {
+    let dir = output_dir();
+    let output = dir.join(format!("{}.exr", name));
+    let image = render(world, samples_per_pixel, &output);
+
+    let reference_path = reference_path(name);
+    if std::env::var_os("UPDATE_REFERENCES").is_some() {
+        fs::create_dir_all(reference_path.parent().unwrap()).unwrap();
+        DynamicImage::ImageRgb32F(image)
+            .save(&reference_path)
+            .unwrap();
+        return;
+    }
+
+    let reference = image::open(&reference_path)
+        .unwrap_or_else(|err| panic!("{}: {}", reference_path.display(), err))
+        .into_rgb32f();
+    assert_eq!(image.dimensions(), reference.dimensions());
+    let error = relative_mse(&image, &reference);
+    if error > THRESHOLD {
+        let diff = dir.join(format!("{}-diff.png", name));
+        difference(&image, &reference).save(&diff).unwrap();
+        panic!(
+            "{}: relative MSE {:.5} exceeds {}, see {} and {}",
+            name,
+            error,
+            THRESHOLD,
+            output.display(),
+            diff.display()
+        );
+    }
+}
+
+#[test]
+fn random_scene() {
+    check("random_scene", 0, 32);
+}
+
+#[test]
+fn two_spheres() {
+    check("two_spheres", 1, 32);
+}
+
+#[test]
+fn two_perlin_spheres() {
+    check("two_perlin_spheres", 2, 32);
+}
+
+#[test]
+fn earth() {
+    check("earth", 3, 32);
+}
+
+#[test]
+fn simple_light() {
+    check("simple_light", 4, 32);
+}
+
+#[test]
+fn cornell_box() {
+    check("cornell_box", 5, 32);
+}
+
+#[test]
+fn cornell_smoke() {
+    check("cornell_smoke", 6, 32);
+}
+
+#[test]
+fn final_scene() {
+    check("final_scene", 7, 128);
+}
+
+#[test]
+fn forest() {
+    check("forest", 8, 32);
+}